@@ -0,0 +1,906 @@
+use crate::feed_auth::{PrefixedSecrets, Secrets};
+use crate::feed_state_memory;
+use crate::http::{FetchClient, HttpClient};
+use crate::memory::{Memory, NamespacedMemory};
+use crate::notion;
+use crate::opml;
+use crate::subscribe::{
+    self, CacheValidators, FetchOptions, FetchedRss, SubscribeListError, SubscribedError,
+    SubscribedRSS,
+};
+use crate::tenant;
+use crate::websub::{self, WebSubOptions};
+use serde::Serialize;
+use std::fmt;
+use worker::{Env, Headers, Request, Response, Result, RouteContext};
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotFound(String),
+    Conflict(String),
+    Invalid(String),
+    Unreachable(SubscribedError),
+    Store(SubscribeListError),
+    UnknownTenant(String),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::NotFound(rss_url) => write!(f, "{} is not subscribed", rss_url),
+            AdminError::Conflict(rss_url) => write!(f, "{} is already subscribed", rss_url),
+            AdminError::Invalid(message) => write!(f, "{}", message),
+            AdminError::Unreachable(err) => write!(f, "failed to read the feed: {:?}", err),
+            AdminError::Store(err) => write!(f, "{}", err),
+            AdminError::UnknownTenant(id) => write!(f, "tenant {} is not configured", id),
+        }
+    }
+}
+
+impl AdminError {
+    fn status(&self) -> u16 {
+        match self {
+            AdminError::NotFound(_) => 404,
+            AdminError::Conflict(_) => 409,
+            AdminError::Invalid(_) => 400,
+            AdminError::Unreachable(_) => 422,
+            AdminError::Store(_) => 500,
+            AdminError::UnknownTenant(_) => 404,
+        }
+    }
+
+    fn into_response(self) -> Result<Response> {
+        Response::error(self.to_string(), self.status())
+    }
+}
+
+async fn load(
+    memory: &impl Memory,
+    fallback: Option<String>,
+) -> std::result::Result<Vec<SubscribedRSS>, AdminError> {
+    match subscribe::load_subscribe_list(memory, fallback).await {
+        Ok(list) => Ok(list),
+        Err(err) => Err(AdminError::Store(err)),
+    }
+}
+
+async fn save(
+    memory: &impl Memory,
+    list: &[SubscribedRSS],
+) -> std::result::Result<(), AdminError> {
+    match subscribe::save_subscribe_list(memory, list).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(AdminError::Store(err)),
+    }
+}
+
+/// Makes sure `subscription` is well-formed, sends its items to one of
+/// `targets` and points at a feed `Rss::from_xml` can read before it is
+/// saved. Returns the subscription with the URL the feed really is at, when
+/// it moved or when a web page linking to it was given.
+async fn check(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    options: &FetchOptions,
+    targets: &[String],
+    mut subscription: SubscribedRSS,
+) -> std::result::Result<SubscribedRSS, AdminError> {
+    if let Err(message) = subscription.validate() {
+        return Err(AdminError::Invalid(message));
+    }
+    if let Some(target) = &subscription.target {
+        if !targets.contains(target) {
+            return Err(AdminError::Invalid(format!(
+                "target {} is not configured",
+                target
+            )));
+        }
+    }
+
+    match subscription
+        .fetch_rss(client, secrets, &CacheValidators::default(), options)
+        .await
+    {
+        Ok(FetchedRss::Modified { moved_to, .. }) => {
+            if let Some(moved_to) = moved_to {
+                subscription.rss_url = moved_to;
+            }
+            Ok(subscription)
+        }
+        Ok(FetchedRss::NotModified { .. }) => {
+            Err(AdminError::Unreachable(SubscribedError::HttpStatus(304)))
+        }
+        Err(err) => Err(AdminError::Unreachable(err)),
+    }
+}
+
+fn is_subscribed(list: &[SubscribedRSS], rss_url: &str, except: Option<&str>) -> bool {
+    list.iter()
+        .any(|listed| listed.rss_url == rss_url && Some(listed.rss_url.as_str()) != except)
+}
+
+pub async fn add_subscription(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    options: &FetchOptions,
+    memory: &impl Memory,
+    fallback: Option<String>,
+    targets: &[String],
+    subscription: SubscribedRSS,
+) -> std::result::Result<SubscribedRSS, AdminError> {
+    let mut list = load(memory, fallback).await?;
+
+    if is_subscribed(&list, &subscription.rss_url, None) {
+        return Err(AdminError::Conflict(subscription.rss_url));
+    }
+
+    let subscription = check(client, secrets, options, targets, subscription).await?;
+
+    if is_subscribed(&list, &subscription.rss_url, None) {
+        return Err(AdminError::Conflict(subscription.rss_url));
+    }
+
+    list.push(subscription.clone());
+    save(memory, &list).await?;
+
+    Ok(subscription)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn update_subscription(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    options: &FetchOptions,
+    memory: &impl Memory,
+    fallback: Option<String>,
+    targets: &[String],
+    rss_url: &str,
+    subscription: SubscribedRSS,
+) -> std::result::Result<SubscribedRSS, AdminError> {
+    let mut list = load(memory, fallback).await?;
+
+    let index = match list.iter().position(|listed| listed.rss_url == rss_url) {
+        Some(index) => index,
+        None => return Err(AdminError::NotFound(rss_url.to_string())),
+    };
+
+    if is_subscribed(&list, &subscription.rss_url, Some(rss_url)) {
+        return Err(AdminError::Conflict(subscription.rss_url));
+    }
+
+    let subscription = check(client, secrets, options, targets, subscription).await?;
+
+    if is_subscribed(&list, &subscription.rss_url, Some(rss_url)) {
+        return Err(AdminError::Conflict(subscription.rss_url));
+    }
+
+    list[index] = subscription.clone();
+    save(memory, &list).await?;
+
+    Ok(subscription)
+}
+
+pub async fn set_paused(
+    memory: &impl Memory,
+    fallback: Option<String>,
+    rss_url: &str,
+    paused: bool,
+) -> std::result::Result<SubscribedRSS, AdminError> {
+    let mut list = load(memory, fallback).await?;
+
+    let subscription = match list.iter_mut().find(|listed| listed.rss_url == rss_url) {
+        Some(subscription) => subscription,
+        None => return Err(AdminError::NotFound(rss_url.to_string())),
+    };
+    subscription.paused = paused;
+    let subscription = subscription.clone();
+
+    save(memory, &list).await?;
+
+    Ok(subscription)
+}
+
+/// Lets a feed disabled for failing too often be fetched again right away.
+pub async fn reset_feed_health(
+    memory: &impl Memory,
+    rss_url: &str,
+) -> std::result::Result<(), AdminError> {
+    let mut feed_state = feed_state_memory::get_feed_state(memory, rss_url).await;
+    feed_state.record_success();
+
+    match feed_state_memory::put_feed_state(memory, rss_url, &feed_state).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(AdminError::Store(SubscribeListError::WorkerError(err))),
+    }
+}
+
+/// Removes a subscription along with the state of its feed, so that the
+/// feed starts afresh if it is subscribed again. The hub of the feed is
+/// asked to stop pushing it when `websub` is set.
+pub async fn delete_subscription(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    feed_state_memory: &impl Memory,
+    fallback: Option<String>,
+    rss_url: &str,
+    websub: Option<&WebSubOptions>,
+) -> std::result::Result<SubscribedRSS, AdminError> {
+    let mut list = load(memory, fallback).await?;
+
+    let index = match list.iter().position(|listed| listed.rss_url == rss_url) {
+        Some(index) => index,
+        None => return Err(AdminError::NotFound(rss_url.to_string())),
+    };
+    let subscription = list.remove(index);
+
+    save(memory, &list).await?;
+
+    if let Some(websub) = websub {
+        let feed_state = feed_state_memory::get_feed_state(feed_state_memory, rss_url).await;
+        match websub::unsubscribe(client, rss_url, &feed_state, websub).await {
+            Ok(_) => {}
+            // The hub stops pushing once the lease ends, and the callback
+            // answers 410 meanwhile
+            Err(err) => worker::console_log!("failed to unsubscribe {}: {:?}", rss_url, err),
+        };
+    }
+
+    match feed_state_memory.delete(rss_url).await {
+        Ok(_) => Ok(subscription),
+        Err(err) => Err(AdminError::Store(SubscribeListError::WorkerError(err))),
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct InvalidOpmlEntry {
+    pub rss_url: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct OpmlImportReport {
+    pub imported: Vec<String>,
+    pub duplicates: Vec<String>,
+    pub invalid: Vec<InvalidOpmlEntry>,
+}
+
+/// Adds the feeds of an OPML document to the subscriptions, using their
+/// outline categories as tags. Unlike `add_subscription` the feeds are not
+/// fetched, since a whole reader export would not fit in a single request.
+pub async fn import_opml(
+    memory: &impl Memory,
+    fallback: Option<String>,
+    xml: &str,
+) -> std::result::Result<OpmlImportReport, AdminError> {
+    let feeds = match opml::parse_opml(xml) {
+        Ok(feeds) => feeds,
+        Err(err) => {
+            return Err(AdminError::Invalid(format!(
+                "request body should be an OPML document: {:?}",
+                err
+            )))
+        }
+    };
+
+    let mut list = load(memory, fallback).await?;
+    let mut report = OpmlImportReport::default();
+
+    for feed in feeds {
+        let subscription = SubscribedRSS {
+            rss_url: feed.rss_url,
+            tags: feed.categories,
+            ..SubscribedRSS::default()
+        };
+
+        if let Err(reason) = subscription.validate() {
+            report.invalid.push(InvalidOpmlEntry {
+                rss_url: subscription.rss_url,
+                reason,
+            });
+            continue;
+        }
+
+        if list
+            .iter()
+            .any(|listed| listed.rss_url == subscription.rss_url)
+        {
+            report.duplicates.push(subscription.rss_url);
+            continue;
+        }
+
+        report.imported.push(subscription.rss_url.clone());
+        list.push(subscription);
+    }
+
+    if !report.imported.is_empty() {
+        save(memory, &list).await?;
+    }
+
+    Ok(report)
+}
+
+fn url_param(req: &Request) -> Result<Option<String>> {
+    let url = req.url()?;
+    let rss_url = url
+        .query_pairs()
+        .find(|(key, _)| key == "url")
+        .map(|(_, value)| value.to_string());
+    Ok(rss_url)
+}
+
+/// Where the subscriptions a request is about are kept: under the prefix of
+/// the tenant named by the `tenant` query parameter, or of the default
+/// workspace, which falls back to `SUBSCRIPTION_LIST`.
+struct Scope {
+    prefix: String,
+    fallback: Option<String>,
+    /// The names subscriptions can send their items to.
+    targets: Vec<String>,
+    /// Private feeds can only read the secrets starting with this.
+    secret_prefix: String,
+}
+
+async fn scope(
+    req: &Request,
+    env: &Env,
+    memory: &impl Memory,
+) -> Result<std::result::Result<Scope, AdminError>> {
+    let url = req.url()?;
+    let id = url
+        .query_pairs()
+        .find(|(key, _)| key == "tenant")
+        .map(|(_, value)| value.to_string());
+
+    let scope = match id {
+        Some(id) => match tenant::all_tenants(env, memory)
+            .await
+            .into_iter()
+            .find(|tenant| tenant.id == id)
+        {
+            Some(tenant) => Ok(Scope {
+                prefix: tenant.prefix(),
+                fallback: None,
+                targets: tenant.targets.keys().cloned().collect(),
+                secret_prefix: tenant.secret_prefix(),
+            }),
+            None => Err(AdminError::UnknownTenant(id)),
+        },
+        None => Ok(Scope {
+            prefix: String::new(),
+            fallback: subscribe::subscription_list_fallback(env),
+            targets: notion::targets_from_env(env).keys().cloned().collect(),
+            secret_prefix: String::new(),
+        }),
+    };
+    Ok(scope)
+}
+
+async fn subscription_body(req: &mut Request) -> std::result::Result<SubscribedRSS, AdminError> {
+    match req.json::<SubscribedRSS>().await {
+        Ok(subscription) => Ok(subscription),
+        Err(err) => Err(AdminError::Invalid(format!(
+            "request body should be a subscription: {}",
+            err
+        ))),
+    }
+}
+
+pub async fn list_subscriptions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    match load(&NamespacedMemory::new(&kv, scope.prefix), scope.fallback).await {
+        Ok(list) => Response::from_json(&list),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub async fn post_subscription(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let subscription = match subscription_body(&mut req).await {
+        Ok(subscription) => subscription,
+        Err(err) => return err.into_response(),
+    };
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    match add_subscription(
+        &FetchClient,
+        &PrefixedSecrets::new(&ctx.env, scope.secret_prefix),
+        &FetchOptions::from_env(&ctx.env),
+        &NamespacedMemory::new(&kv, scope.prefix),
+        scope.fallback,
+        &scope.targets,
+        subscription,
+    )
+    .await
+    {
+        Ok(subscription) => Ok(Response::from_json(&subscription)?.with_status(201)),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub async fn put_subscription(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let rss_url = match url_param(&req)? {
+        Some(rss_url) => rss_url,
+        None => return Response::error("url query parameter is required", 400),
+    };
+
+    let subscription = match subscription_body(&mut req).await {
+        Ok(subscription) => subscription,
+        Err(err) => return err.into_response(),
+    };
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    match update_subscription(
+        &FetchClient,
+        &PrefixedSecrets::new(&ctx.env, scope.secret_prefix),
+        &FetchOptions::from_env(&ctx.env),
+        &NamespacedMemory::new(&kv, scope.prefix),
+        scope.fallback,
+        &scope.targets,
+        &rss_url,
+        subscription,
+    )
+    .await
+    {
+        Ok(subscription) => Response::from_json(&subscription),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn pause_or_resume(req: Request, ctx: RouteContext<()>, paused: bool) -> Result<Response> {
+    let rss_url = match url_param(&req)? {
+        Some(rss_url) => rss_url,
+        None => return Response::error("url query parameter is required", 400),
+    };
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    let subscription = match set_paused(
+        &NamespacedMemory::new(&kv, scope.prefix.clone()),
+        scope.fallback,
+        &rss_url,
+        paused,
+    )
+    .await
+    {
+        Ok(subscription) => subscription,
+        Err(err) => return err.into_response(),
+    };
+
+    if !paused {
+        let feed_state_kv = ctx.kv("LATEST_PUSHED_DATES")?;
+        let feed_state_memory = NamespacedMemory::new(&feed_state_kv, scope.prefix);
+        if let Err(err) = reset_feed_health(&feed_state_memory, &rss_url).await {
+            return err.into_response();
+        }
+    }
+
+    Response::from_json(&subscription)
+}
+
+pub async fn pause_subscription(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    pause_or_resume(req, ctx, true).await
+}
+
+pub async fn resume_subscription(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    pause_or_resume(req, ctx, false).await
+}
+
+pub async fn remove_subscription(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let rss_url = match url_param(&req)? {
+        Some(rss_url) => rss_url,
+        None => return Response::error("url query parameter is required", 400),
+    };
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    let feed_state_kv = ctx.kv("LATEST_PUSHED_DATES")?;
+    // Only the feeds of the default workspace are subscribed to their hubs
+    let websub = if scope.prefix.is_empty() {
+        WebSubOptions::from_env(&ctx.env)
+    } else {
+        None
+    };
+
+    match delete_subscription(
+        &FetchClient,
+        &NamespacedMemory::new(&kv, scope.prefix.clone()),
+        &NamespacedMemory::new(&feed_state_kv, scope.prefix),
+        scope.fallback,
+        &rss_url,
+        websub.as_ref(),
+    )
+    .await
+    {
+        Ok(subscription) => Response::from_json(&subscription),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub async fn post_opml(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+    let xml = req.text().await?;
+
+    match import_opml(
+        &NamespacedMemory::new(&kv, scope.prefix),
+        scope.fallback,
+        &xml,
+    )
+    .await
+    {
+        Ok(report) => Response::from_json(&report),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub async fn get_opml(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+    let scope = match scope(&req, &ctx.env, &kv).await? {
+        Ok(scope) => scope,
+        Err(err) => return err.into_response(),
+    };
+
+    match load(&NamespacedMemory::new(&kv, scope.prefix), scope.fallback).await {
+        Ok(list) => {
+            let mut headers = Headers::new();
+            headers.set("Content-Type", "text/x-opml; charset=utf-8")?;
+            Ok(Response::ok(opml::to_opml(&list))?.with_headers(headers))
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_auth::mock::SecretStore;
+    use crate::feed_state_memory::FeedState;
+    use crate::http::mock::MockClient;
+    use crate::http::HttpResponse;
+    use crate::memory::mock::MemoryStore;
+    use wasm_bindgen_test::*;
+    use worker::Method;
+
+    const FEED_URL: &str = "https://example.com/feed.xml";
+    const FEED: &str = "<rss version=\"2.0\"><channel><title>blog title</title></channel></rss>";
+    const FALLBACK: &str = r#"[{"rss_url": "https://web.dev/feed.xml", "tags": ["web"]}]"#;
+    const HUB_URL: &str = "https://hub.example.com/";
+    const MINUTE: u64 = 60 * 1000;
+
+    #[wasm_bindgen_test]
+    async fn add_readable_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+
+        add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            Some(FALLBACK.to_string()),
+            &[],
+            SubscribedRSS::new(FEED_URL, vec!["blog"]),
+        )
+        .await
+        .unwrap();
+
+        let list = subscribe::load_subscribe_list(&memory, None).await.unwrap();
+        assert_eq!(
+            list,
+            vec![
+                SubscribedRSS::new("https://web.dev/feed.xml", vec!["web"]),
+                SubscribedRSS::new(FEED_URL, vec!["blog"]),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn fetch_with_configured_options() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+        let options = FetchOptions {
+            user_agent: "custom agent".to_string(),
+            ..FetchOptions::default()
+        };
+
+        add_subscription(
+            &client,
+            &SecretStore::new(),
+            &options,
+            &memory,
+            None,
+            &[],
+            SubscribedRSS::new(FEED_URL, vec![]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            client.requests()[0].header("User-Agent"),
+            Some("custom agent")
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_unreadable_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, "<html>"));
+        let memory = MemoryStore::new();
+
+        let err = add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            None,
+            &[],
+            SubscribedRSS::new(FEED_URL, vec![]),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, AdminError::Unreachable(_)));
+        assert!(memory.value("subscriptions").is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_duplicated_feed() {
+        let client = MockClient::new();
+        let memory = MemoryStore::new();
+
+        let err = add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            Some(FALLBACK.to_string()),
+            &[],
+            SubscribedRSS::new("https://web.dev/feed.xml", vec![]),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, AdminError::Conflict(_)));
+        assert!(client.requests().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_unknown_target() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+        let subscription = |target: &str| SubscribedRSS {
+            target: Some(target.to_string()),
+            ..SubscribedRSS::new(FEED_URL, vec!["blog"])
+        };
+
+        let err = add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            None,
+            &["releases".to_string()],
+            subscription("relases"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AdminError::Invalid(_)));
+        assert_eq!(err.status(), 400);
+        assert!(client.requests().is_empty());
+
+        add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            None,
+            &["releases".to_string()],
+            subscription("releases"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn pause_and_delete_feed() {
+        let memory = MemoryStore::new();
+
+        let paused = set_paused(
+            &memory,
+            Some(FALLBACK.to_string()),
+            "https://web.dev/feed.xml",
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(paused.paused);
+
+        delete_subscription(
+            &MockClient::new(),
+            &memory,
+            &MemoryStore::new(),
+            None,
+            "https://web.dev/feed.xml",
+            None,
+        )
+        .await
+        .unwrap();
+        let list = subscribe::load_subscribe_list(&memory, Some(FALLBACK.to_string()))
+            .await
+            .unwrap();
+        assert!(list.is_empty());
+
+        let err = delete_subscription(
+            &MockClient::new(),
+            &memory,
+            &MemoryStore::new(),
+            None,
+            "https://web.dev/feed.xml",
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AdminError::NotFound(_)));
+    }
+
+    #[wasm_bindgen_test]
+    async fn forget_state_of_deleted_feed() {
+        let client = MockClient::new();
+        client.on(Method::Post, HUB_URL, HttpResponse::new(202, ""));
+        let memory = MemoryStore::new();
+        let feed_state_memory = MemoryStore::new();
+        let feed_state = FeedState {
+            latest_pushed_date: Some(42),
+            disabled: true,
+            websub_hub: Some(HUB_URL.to_string()),
+            websub_topic: Some(FEED_URL.to_string()),
+            websub_lease_expires_at: Some(42),
+            ..FeedState::default()
+        };
+        feed_state_memory::put_feed_state(&feed_state_memory, FEED_URL, &feed_state)
+            .await
+            .unwrap();
+        let websub = WebSubOptions {
+            callback_url: "https://worker.example.com/websub/callback".to_string(),
+            secret: "websub secret".to_string(),
+            lease_seconds: 600,
+            renew_before_millis: MINUTE,
+        };
+
+        delete_subscription(
+            &client,
+            &memory,
+            &feed_state_memory,
+            Some(format!(r#"[{{"rss_url": "{}", "tags": []}}]"#, FEED_URL)),
+            FEED_URL,
+            Some(&websub),
+        )
+        .await
+        .unwrap();
+
+        assert!(feed_state_memory.value(FEED_URL).is_none());
+        let requests = client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url, HUB_URL);
+        assert!(requests[0]
+            .body
+            .as_ref()
+            .unwrap()
+            .contains("hub.mode=unsubscribe"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn report_duplicated_and_invalid_opml_entries() {
+        let memory = MemoryStore::new();
+
+        let report = import_opml(
+            &memory,
+            Some(FALLBACK.to_string()),
+            "<opml version=\"2.0\"><body><outline text=\"Tech\"><outline xmlUrl=\"https://web.dev/feed.xml\"/><outline xmlUrl=\"https://example.com/feed.xml\"/><outline xmlUrl=\"https://example.com/feed.xml\"/><outline xmlUrl=\"feed.xml\"/></outline></body></opml>",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.imported, vec![FEED_URL.to_string()]);
+        assert_eq!(
+            report.duplicates,
+            vec![
+                "https://web.dev/feed.xml".to_string(),
+                FEED_URL.to_string()
+            ]
+        );
+        assert_eq!(report.invalid.len(), 1);
+        assert_eq!(report.invalid[0].rss_url, "feed.xml");
+
+        let list = subscribe::load_subscribe_list(&memory, None).await.unwrap();
+        assert_eq!(list[1], SubscribedRSS::new(FEED_URL, vec!["Tech"]));
+    }
+
+    #[wasm_bindgen_test]
+    async fn subscribe_to_feed_discovered_on_website() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            "https://example.com/",
+            HttpResponse::new(
+                200,
+                "<!DOCTYPE html><html><head><link rel=\"alternate\" type=\"application/rss+xml\" href=\"/feed.xml\"></head></html>",
+            )
+            .with_header("Content-Type", "text/html"),
+        );
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+
+        let subscription = add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            None,
+            &[],
+            SubscribedRSS::new("https://example.com/", vec!["blog"]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(subscription, SubscribedRSS::new(FEED_URL, vec!["blog"]));
+        let list = subscribe::load_subscribe_list(&memory, None).await.unwrap();
+        assert_eq!(list, vec![SubscribedRSS::new(FEED_URL, vec!["blog"])]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_website_without_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            "https://example.com/",
+            HttpResponse::new(200, "<!DOCTYPE html><html><head></head></html>"),
+        );
+        let memory = MemoryStore::new();
+
+        let err = add_subscription(
+            &client,
+            &SecretStore::new(),
+            &FetchOptions::default(),
+            &memory,
+            None,
+            &[],
+            SubscribedRSS::new("https://example.com/", vec![]),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            err,
+            AdminError::Unreachable(SubscribedError::NoFeedFound)
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+use worker::{Env, Headers, Request, Response, Result};
+
+/// Called by WebSub hubs, which authenticate with the signature of the
+/// content they push instead.
+pub const WEBSUB_CALLBACK_PATH: &str = "/websub/callback";
+
+/// Where Notion sends users back after the consent screen, the state of the
+/// authorization stands for the admin token.
+pub const OAUTH_CALLBACK_PATH: &str = "/oauth/callback";
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// No usable `Authorization: Bearer <token>` header was sent.
+    MissingCredentials,
+    /// A token was sent but it is not the `admin_token` secret.
+    InvalidCredentials,
+}
+
+/// Checks the `Authorization: Bearer <token>` header against the
+/// `admin_token` secret before the router runs any handler. Returns the
+/// response to send back when the request is not allowed to go further.
+pub fn authorize(req: &Request, env: &Env) -> Result<Option<Response>> {
+    let admin_token = match env.secret("admin_token") {
+        Ok(admin_token) => Some(admin_token.to_string()),
+        Err(_) => {
+            worker::console_log!("admin_token secret is not set, rejecting request");
+            None
+        }
+    };
+
+    let authorization = req.headers().get("Authorization")?;
+
+    match check_authorization(authorization.as_deref(), admin_token.as_deref()) {
+        Ok(_) => Ok(None),
+        Err(AuthError::MissingCredentials) => {
+            let mut headers = Headers::new();
+            headers.set("WWW-Authenticate", "Bearer")?;
+            Ok(Some(
+                Response::error("unauthorized", 401)?.with_headers(headers),
+            ))
+        }
+        Err(AuthError::InvalidCredentials) => Ok(Some(Response::error("forbidden", 403)?)),
+    }
+}
+
+/// Routes called by third parties, which cannot know the admin token.
+pub fn is_public(path: &str) -> bool {
+    path == WEBSUB_CALLBACK_PATH || path == OAUTH_CALLBACK_PATH
+}
+
+pub fn check_authorization(
+    authorization: Option<&str>,
+    admin_token: Option<&str>,
+) -> std::result::Result<(), AuthError> {
+    let token = match authorization {
+        Some(authorization) => match authorization.strip_prefix("Bearer ") {
+            Some(token) if !token.trim().is_empty() => token.trim(),
+            _ => return Err(AuthError::MissingCredentials),
+        },
+        None => return Err(AuthError::MissingCredentials),
+    };
+
+    // Without a configured token nobody is allowed in
+    match admin_token {
+        Some(admin_token) if !admin_token.is_empty() => {
+            if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+                Ok(())
+            } else {
+                Err(AuthError::InvalidCredentials)
+            }
+        }
+        _ => Err(AuthError::InvalidCredentials),
+    }
+}
+
+/// Compares without returning early, so the response time does not tell how
+/// much of the token was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_admin_token() {
+        assert_eq!(
+            check_authorization(Some("Bearer secret"), Some("secret")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn reject_missing_credentials() {
+        assert_eq!(
+            check_authorization(None, Some("secret")),
+            Err(AuthError::MissingCredentials)
+        );
+        assert_eq!(
+            check_authorization(Some("Basic c2VjcmV0"), Some("secret")),
+            Err(AuthError::MissingCredentials)
+        );
+        assert_eq!(
+            check_authorization(Some("Bearer "), Some("secret")),
+            Err(AuthError::MissingCredentials)
+        );
+    }
+
+    #[test]
+    fn only_callbacks_are_public() {
+        assert!(is_public("/websub/callback"));
+        assert!(is_public("/oauth/callback"));
+        assert!(!is_public("/"));
+        assert!(!is_public("/subscriptions"));
+        assert!(!is_public("/oauth/authorize"));
+    }
+
+    #[test]
+    fn reject_wrong_token() {
+        assert_eq!(
+            check_authorization(Some("Bearer secreT"), Some("secret")),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            check_authorization(Some("Bearer secret"), None),
+            Err(AuthError::InvalidCredentials)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use worker::Env;
+
+/// Notion rejects select options longer than this.
+const MAX_OPTION_LENGTH: usize = 100;
+
+/// How the categories of feed items become options of the `categories`
+/// multi-select, read as JSON from the `CATEGORIES` variable.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CategoryOptions {
+    /// Lowercases categories before they are renamed.
+    pub lowercase: bool,
+    /// Renames categories, e.g. `{ "JS": "javascript" }`. Keys are compared
+    /// case-insensitively.
+    pub aliases: HashMap<String, String>,
+    /// Only keeps these categories when set, compared case-insensitively.
+    pub allow: Option<Vec<String>>,
+    /// Keeps at most this many categories per item.
+    pub max: Option<usize>,
+}
+
+impl CategoryOptions {
+    /// `None` when `CATEGORIES` is not set, categories are then not synced
+    /// so databases without the `categories` property keep working.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let categories = match env.var("CATEGORIES") {
+            Ok(categories) if !categories.to_string().trim().is_empty() => categories.to_string(),
+            _ => return None,
+        };
+
+        match serde_json::from_str(&categories) {
+            Ok(options) => Some(options),
+            Err(err) => {
+                worker::console_log!(
+                    "CATEGORIES is not valid, categories are not synced: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// The options `categories` end up as, in their order and without
+    /// duplicates.
+    pub fn normalize(&self, categories: &[String]) -> Vec<String> {
+        let mut options: Vec<String> = vec![];
+
+        for category in categories {
+            let mut category = sanitize(category);
+            if self.lowercase {
+                category = category.to_lowercase();
+            }
+            if let Some((_, alias)) = self
+                .aliases
+                .iter()
+                .find(|(name, _)| sanitize(name).to_lowercase() == category.to_lowercase())
+            {
+                category = sanitize(alias);
+            }
+            if category.is_empty() {
+                continue;
+            }
+
+            if let Some(allow) = &self.allow {
+                if !allow
+                    .iter()
+                    .any(|allowed| sanitize(allowed).to_lowercase() == category.to_lowercase())
+                {
+                    continue;
+                }
+            }
+            if options
+                .iter()
+                .any(|option| option.to_lowercase() == category.to_lowercase())
+            {
+                continue;
+            }
+
+            options.push(category);
+        }
+
+        if let Some(max) = self.max {
+            options.truncate(max);
+        }
+        options
+    }
+}
+
+/// Removes the commas Notion forbids in select options, and the whitespace
+/// feeds pad categories with.
+fn sanitize(category: &str) -> String {
+    category
+        .replace(',', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .take(MAX_OPTION_LENGTH)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn categories(categories: &[&str]) -> Vec<String> {
+        categories
+            .iter()
+            .map(|category| category.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn remove_commas_and_whitespace() {
+        assert_eq!(sanitize("  Rust,WebAssembly \n"), "Rust WebAssembly");
+        assert_eq!(sanitize(","), "");
+        assert_eq!(sanitize(&"a".repeat(120)).len(), MAX_OPTION_LENGTH);
+    }
+
+    #[test]
+    fn keep_categories_by_default() {
+        assert_eq!(
+            CategoryOptions::default().normalize(&categories(&["Rust", "rust", "Web, CSS", " "])),
+            categories(&["Rust", "Web CSS"])
+        );
+    }
+
+    #[test]
+    fn rename_and_allow_categories() {
+        let options: CategoryOptions = serde_json::from_str(
+            r#"{ "lowercase": true, "aliases": { "JS": "javascript" }, "allow": ["JavaScript", "rust"], "max": 1 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            options.normalize(&categories(&["Go", "JS", "Rust"])),
+            categories(&["javascript"])
+        );
+
+        let options = CategoryOptions {
+            max: None,
+            ..options
+        };
+        assert_eq!(
+            options.normalize(&categories(&["Go", "JS", "Rust", "JavaScript"])),
+            categories(&["javascript", "rust"])
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::http::HttpRequest;
+use serde::{Deserialize, Serialize};
+use worker::{Env, Url};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// How a private feed is authenticated. Only the name of the Worker secret
+/// holding the credential is stored with the subscription, never the
+/// credential itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedAuth {
+    /// `Authorization: Basic`, the password is read from `password_secret`.
+    Basic {
+        username: String,
+        password_secret: String,
+    },
+    /// `Authorization: Bearer`, the token is read from `token_secret`.
+    Bearer { token_secret: String },
+    /// A custom header, its value is read from `value_secret`.
+    Header { name: String, value_secret: String },
+    /// A query string parameter, its value is read from `value_secret`.
+    Query { name: String, value_secret: String },
+}
+
+/// Where the credentials of private feeds are read from. The worker reads
+/// its secrets, tests use `mock::SecretStore`.
+pub trait Secrets {
+    fn secret(&self, name: &str) -> Option<String>;
+}
+
+impl Secrets for Env {
+    fn secret(&self, name: &str) -> Option<String> {
+        match Env::secret(self, name) {
+            Ok(secret) => Some(secret.to_string()),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Only the secrets of `secrets` whose name starts with `prefix`, so that
+/// the feeds of a tenant cannot send the secrets of the worker or of other
+/// tenants to their host.
+pub struct PrefixedSecrets<'a, S: Secrets> {
+    secrets: &'a S,
+    prefix: String,
+}
+
+impl<'a, S: Secrets> PrefixedSecrets<'a, S> {
+    pub fn new(secrets: &'a S, prefix: impl Into<String>) -> Self {
+        Self {
+            secrets,
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl<'a, S: Secrets> Secrets for PrefixedSecrets<'a, S> {
+    fn secret(&self, name: &str) -> Option<String> {
+        if !name.starts_with(&self.prefix) {
+            return None;
+        }
+        self.secrets.secret(name)
+    }
+}
+
+/// The credential of a feed, resolved from its secret. It is deliberately
+/// not `Debug` so it cannot end up in a log line.
+pub struct Credentials {
+    auth: FeedAuth,
+    secret: String,
+}
+
+impl FeedAuth {
+    pub fn secret_name(&self) -> &str {
+        match self {
+            FeedAuth::Basic {
+                password_secret, ..
+            } => password_secret,
+            FeedAuth::Bearer { token_secret } => token_secret,
+            FeedAuth::Header { value_secret, .. } => value_secret,
+            FeedAuth::Query { value_secret, .. } => value_secret,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret_name().trim().is_empty() {
+            return Err("auth should name the secret holding the credential".into());
+        }
+
+        match self {
+            FeedAuth::Basic { username, .. } if username.contains(':') => {
+                Err("basic auth username should not contain colon".into())
+            }
+            FeedAuth::Header { name, .. } | FeedAuth::Query { name, .. }
+                if name.trim().is_empty() =>
+            {
+                Err("auth name should not be empty".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the credential out of `secrets`. Fails with the name of the
+    /// secret when it is not set.
+    pub fn credentials(&self, secrets: &impl Secrets) -> Result<Credentials, String> {
+        match secrets.secret(self.secret_name()) {
+            Some(secret) => Ok(Credentials {
+                auth: self.clone(),
+                secret,
+            }),
+            None => Err(self.secret_name().to_string()),
+        }
+    }
+}
+
+impl Credentials {
+    pub fn apply(&self, request: HttpRequest) -> HttpRequest {
+        match &self.auth {
+            FeedAuth::Basic { username, .. } => request.with_header(
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    base64::encode(format!("{}:{}", username, self.secret))
+                ),
+            ),
+            FeedAuth::Bearer { .. } => {
+                request.with_header("Authorization", format!("Bearer {}", self.secret))
+            }
+            FeedAuth::Header { name, .. } => request.with_header(name, &self.secret),
+            FeedAuth::Query { name, .. } => {
+                let mut request = request;
+                if let Ok(mut url) = Url::parse(&request.url) {
+                    url.query_pairs_mut().append_pair(name, &self.secret);
+                    request.url = url.to_string();
+                }
+                request
+            }
+        }
+    }
+
+    /// Removes the credential from `text`, an error message or a URL sent
+    /// back by the feed, before it is logged or stored.
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+
+        if let FeedAuth::Query { name, .. } = &self.auth {
+            if let Ok(mut url) = Url::parse(text) {
+                let pairs: Vec<(String, String)> = url
+                    .query_pairs()
+                    .filter(|(key, _)| key != name)
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect();
+                if pairs.is_empty() {
+                    url.set_query(None);
+                } else {
+                    url.query_pairs_mut().clear().extend_pairs(pairs);
+                }
+                redacted = url.to_string();
+            }
+        }
+
+        for secret in [self.secret.clone(), encode_query(&self.secret)] {
+            if !secret.is_empty() {
+                redacted = redacted.replace(&secret, REDACTED);
+            }
+        }
+
+        redacted
+    }
+}
+
+fn encode_query(value: &str) -> String {
+    let mut url = Url::parse("https://example.com").unwrap();
+    url.query_pairs_mut().append_pair("", value);
+    url.query()
+        .unwrap_or("")
+        .trim_start_matches('=')
+        .to_string()
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    pub struct SecretStore {
+        secrets: BTreeMap<String, String>,
+    }
+
+    impl SecretStore {
+        pub fn new() -> Self {
+            Self {
+                secrets: BTreeMap::new(),
+            }
+        }
+
+        pub fn with(mut self, name: &str, value: &str) -> Self {
+            self.secrets.insert(name.to_string(), value.to_string());
+            self
+        }
+    }
+
+    impl Secrets for SecretStore {
+        fn secret(&self, name: &str) -> Option<String> {
+            self.secrets.get(name).cloned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::SecretStore;
+    use super::*;
+    use worker::Method;
+
+    const FEED_URL: &str = "https://example.com/feed.xml?lang=en";
+
+    fn credentials(auth: FeedAuth) -> Credentials {
+        auth.credentials(&SecretStore::new().with("feed_secret", "s3cr/t"))
+            .unwrap()
+    }
+
+    #[test]
+    fn authenticate_with_basic_auth() {
+        let request = credentials(FeedAuth::Basic {
+            username: "user".into(),
+            password_secret: "feed_secret".into(),
+        })
+        .apply(HttpRequest::new(Method::Get, FEED_URL));
+
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Basic dXNlcjpzM2NyL3Q=")
+        );
+    }
+
+    #[test]
+    fn authenticate_with_bearer_token_and_header() {
+        let request = credentials(FeedAuth::Bearer {
+            token_secret: "feed_secret".into(),
+        })
+        .apply(HttpRequest::new(Method::Get, FEED_URL));
+        assert_eq!(request.header("Authorization"), Some("Bearer s3cr/t"));
+
+        let request = credentials(FeedAuth::Header {
+            name: "X-Api-Key".into(),
+            value_secret: "feed_secret".into(),
+        })
+        .apply(HttpRequest::new(Method::Get, FEED_URL));
+        assert_eq!(request.header("X-Api-Key"), Some("s3cr/t"));
+    }
+
+    #[test]
+    fn authenticate_with_query() {
+        let credentials = credentials(FeedAuth::Query {
+            name: "token".into(),
+            value_secret: "feed_secret".into(),
+        });
+        let request = credentials.apply(HttpRequest::new(Method::Get, FEED_URL));
+
+        assert_eq!(
+            request.url,
+            "https://example.com/feed.xml?lang=en&token=s3cr%2Ft"
+        );
+        assert_eq!(credentials.redact(&request.url), FEED_URL);
+        assert_eq!(
+            credentials.redact(&format!("failed to fetch {}", request.url)),
+            "failed to fetch https://example.com/feed.xml?lang=en&token=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn only_read_prefixed_secrets() {
+        let secrets = SecretStore::new()
+            .with("alice_feed_token", "alice token")
+            .with("notion_api_key", "notion token");
+        let secrets = PrefixedSecrets::new(&secrets, "alice_");
+
+        assert_eq!(
+            secrets.secret("alice_feed_token"),
+            Some("alice token".to_string())
+        );
+        assert_eq!(secrets.secret("notion_api_key"), None);
+    }
+
+    #[test]
+    fn fail_without_secret() {
+        let auth = FeedAuth::Bearer {
+            token_secret: "missing".into(),
+        };
+
+        assert_eq!(
+            auth.credentials(&SecretStore::new()).err(),
+            Some("missing".to_string())
+        );
+    }
+}
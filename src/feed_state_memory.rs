@@ -0,0 +1,245 @@
+use crate::memory::Memory;
+use crate::rss::PollingHints;
+use serde::{Deserialize, Serialize};
+use worker::{Date, DateInit, Error};
+
+/// What is remembered about a feed between runs, stored as JSON under the
+/// feed URL.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedState {
+    #[serde(default)]
+    pub latest_pushed_date: Option<u64>,
+    /// The ids of the pushed items which have no date, as the watermark
+    /// cannot tell whether they are new.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pushed_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// How many fetches in a row failed.
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// The feed is not fetched before this time, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<u64>,
+    /// The feed kept failing and is not fetched anymore until it is resumed.
+    #[serde(default)]
+    pub disabled: bool,
+    /// When the feed was last fetched successfully, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_fetched_at: Option<u64>,
+    /// The polling hints of the feed and its last response, see
+    /// `polling::next_fetch_at`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_minutes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_interval_minutes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_hours: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_days: Vec<u32>,
+    /// The WebSub hub the feed advertises, and the topic it is subscribed to
+    /// there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websub_hub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websub_topic: Option<String>,
+    /// When the lease granted by the hub ends, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websub_lease_expires_at: Option<u64>,
+    /// When a subscription was last asked to the hub. It is pending until
+    /// the hub verifies it through the callback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websub_requested_at: Option<u64>,
+}
+
+pub struct HealthOptions {
+    /// How long to wait after the first failure. The wait doubles with every
+    /// further failure.
+    pub backoff_base_millis: u64,
+    pub backoff_max_millis: u64,
+    /// Disables the feed after this many failures in a row, 0 never does.
+    pub disable_after_failures: u32,
+}
+
+impl Default for HealthOptions {
+    fn default() -> Self {
+        Self {
+            backoff_base_millis: 30 * 60 * 1000,
+            backoff_max_millis: 24 * 60 * 60 * 1000,
+            disable_after_failures: 10,
+        }
+    }
+}
+
+impl FeedState {
+    pub fn is_due(&self, now: u64) -> bool {
+        match self.next_attempt_at {
+            Some(next_attempt_at) => next_attempt_at <= now,
+            None => true,
+        }
+    }
+
+    /// Returns true when this failure disabled the feed.
+    pub fn record_failure(&mut self, error: String, now: u64, options: &HealthOptions) -> bool {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error);
+
+        let backoff_millis = options
+            .backoff_base_millis
+            .saturating_mul(2u64.saturating_pow(self.consecutive_failures - 1))
+            .min(options.backoff_max_millis);
+        self.next_attempt_at = Some(now.saturating_add(backoff_millis));
+
+        if !self.disabled
+            && options.disable_after_failures > 0
+            && self.consecutive_failures >= options.disable_after_failures
+        {
+            self.disabled = true;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn mark_gone(&mut self) {
+        self.last_error = Some("Gone".to_string());
+        self.next_attempt_at = None;
+        self.disabled = true;
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.last_error = None;
+        self.next_attempt_at = None;
+        self.disabled = false;
+    }
+
+    /// Remembers when the feed was fetched and what it said about polling.
+    /// `hints` is None when the feed was not modified, the hints of the last
+    /// body are kept then.
+    pub fn record_fetch(
+        &mut self,
+        now: u64,
+        max_age_seconds: Option<u64>,
+        hints: Option<&PollingHints>,
+    ) {
+        self.last_fetched_at = Some(now);
+        self.max_age_seconds = max_age_seconds;
+        if let Some(hints) = hints {
+            self.ttl_minutes = hints.ttl_minutes;
+            self.update_interval_minutes = hints.update_interval_minutes;
+            self.skip_hours = hints.skip_hours.clone();
+            self.skip_days = hints.skip_days.clone();
+        }
+    }
+
+    /// Remembers the hub the feed advertises. A subscription to a previous
+    /// hub or topic is forgotten.
+    pub fn record_hub(&mut self, hub_url: Option<String>, topic: String) {
+        let topic = hub_url.as_ref().map(|_| topic);
+        if self.websub_hub != hub_url || self.websub_topic != topic {
+            self.websub_hub = hub_url;
+            self.websub_topic = topic;
+            self.websub_lease_expires_at = None;
+            self.websub_requested_at = None;
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match serde_json::from_str::<FeedState>(value) {
+            Ok(state) => state,
+            // Older versions only stored the latest pushed date as a date string
+            Err(_) => Self {
+                latest_pushed_date: Some(
+                    Date::from(DateInit::String(value.to_string())).as_millis(),
+                ),
+                ..Self::default()
+            },
+        }
+    }
+}
+
+pub async fn get_feed_state(memory: &impl Memory, key: &str) -> FeedState {
+    match memory.get(key).await {
+        Ok(feed_state) => match feed_state {
+            Some(feed_state) => FeedState::parse(&feed_state),
+            None => FeedState::default(),
+        },
+        Err(_) => FeedState::default(),
+    }
+}
+
+pub async fn put_feed_state(
+    memory: &impl Memory,
+    key: &str,
+    feed_state: &FeedState,
+) -> Result<(), Error> {
+    let feed_state = match serde_json::to_string(feed_state) {
+        Ok(feed_state) => feed_state,
+        Err(err) => return Err(Error::from(err)),
+    };
+
+    memory.put(key, &feed_state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1000;
+
+    fn options() -> HealthOptions {
+        HealthOptions {
+            backoff_base_millis: MINUTE,
+            backoff_max_millis: 5 * MINUTE,
+            disable_after_failures: 4,
+        }
+    }
+
+    #[test]
+    fn back_off_exponentially() {
+        let mut feed_state = FeedState::default();
+
+        feed_state.record_failure("err".into(), 0, &options());
+        assert_eq!(feed_state.next_attempt_at, Some(MINUTE));
+        feed_state.record_failure("err".into(), 0, &options());
+        assert_eq!(feed_state.next_attempt_at, Some(2 * MINUTE));
+        feed_state.record_failure("err".into(), 0, &options());
+        assert_eq!(feed_state.next_attempt_at, Some(4 * MINUTE));
+
+        assert!(!feed_state.is_due(3 * MINUTE));
+        assert!(feed_state.is_due(4 * MINUTE));
+    }
+
+    #[test]
+    fn cap_backoff() {
+        let mut feed_state = FeedState {
+            consecutive_failures: 60,
+            ..FeedState::default()
+        };
+
+        feed_state.record_failure("err".into(), 0, &options());
+        assert_eq!(feed_state.next_attempt_at, Some(5 * MINUTE));
+    }
+
+    #[test]
+    fn disable_after_threshold() {
+        let mut feed_state = FeedState::default();
+
+        assert!(!feed_state.record_failure("err".into(), 0, &options()));
+        assert!(!feed_state.record_failure("err".into(), 0, &options()));
+        assert!(!feed_state.record_failure("err".into(), 0, &options()));
+        assert!(feed_state.record_failure("err".into(), 0, &options()));
+        assert!(feed_state.disabled);
+        assert!(!feed_state.record_failure("err".into(), 0, &options()));
+
+        feed_state.record_success();
+        assert_eq!(feed_state, FeedState::default());
+    }
+}
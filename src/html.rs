@@ -0,0 +1,305 @@
+use worker::Url;
+
+const FEED_TYPES: [&str; 2] = ["application/rss+xml", "application/atom+xml"];
+
+/// A start tag found in an HTML document.
+#[derive(Debug, PartialEq)]
+pub struct HtmlTag {
+    /// Lowercased.
+    pub name: String,
+    /// Lowercased names, with the entities of values decoded.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl HtmlTag {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute_name, _)| attribute_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Scans `html` for the start tags named `name`. This is not an HTML parser,
+/// web pages are rarely well-formed enough for `roxmltree`. It only knows
+/// about tags, their attributes and comments, and skips the content of
+/// `script` and `style` elements.
+pub fn find_tags(html: &str, name: &str) -> Vec<HtmlTag> {
+    let mut tags = vec![];
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = match comment.find("-->") {
+                Some(end) => &comment[end + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        let (tag, after) = match parse_tag(rest) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        rest = after;
+
+        // Nothing in scripts and styles is markup
+        if tag.name == "script" || tag.name == "style" {
+            let closing_tag = format!("</{}", tag.name);
+            rest = match rest.to_ascii_lowercase().find(&closing_tag) {
+                Some(end) => &rest[end..],
+                None => "",
+            };
+        }
+
+        if tag.name.eq_ignore_ascii_case(name) {
+            tags.push(tag);
+        }
+    }
+
+    tags
+}
+
+/// Parses the tag starting right after `<`, and returns what follows it.
+fn parse_tag(input: &str) -> Option<(HtmlTag, &str)> {
+    let bytes = input.as_bytes();
+    let mut position = 0;
+
+    while position < bytes.len() && bytes[position].is_ascii_alphanumeric() {
+        position += 1;
+    }
+    if position == 0 {
+        return None;
+    }
+    let name = input[..position].to_ascii_lowercase();
+    let mut attributes = vec![];
+
+    loop {
+        while position < bytes.len()
+            && (bytes[position].is_ascii_whitespace() || bytes[position] == b'/')
+        {
+            position += 1;
+        }
+        if position >= bytes.len() {
+            break;
+        }
+        if bytes[position] == b'>' {
+            position += 1;
+            break;
+        }
+
+        let name_start = position;
+        while position < bytes.len()
+            && !bytes[position].is_ascii_whitespace()
+            && !matches!(bytes[position], b'=' | b'>' | b'/')
+        {
+            position += 1;
+        }
+        let attribute_name = input[name_start..position].to_ascii_lowercase();
+
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position >= bytes.len() || bytes[position] != b'=' {
+            attributes.push((attribute_name, String::new()));
+            continue;
+        }
+        position += 1;
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+
+        let value = match bytes.get(position) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let value_start = position + 1;
+                let value_end = match bytes[value_start..].iter().position(|&byte| byte == quote) {
+                    Some(length) => value_start + length,
+                    None => bytes.len(),
+                };
+                position = (value_end + 1).min(bytes.len());
+                &input[value_start..value_end]
+            }
+            _ => {
+                let value_start = position;
+                while position < bytes.len()
+                    && !bytes[position].is_ascii_whitespace()
+                    && bytes[position] != b'>'
+                {
+                    position += 1;
+                }
+                &input[value_start..position]
+            }
+        };
+        attributes.push((attribute_name, decode_entities(value)));
+    }
+
+    Some((HtmlTag { name, attributes }, &input[position..]))
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#34;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Whether a response is a web page rather than a feed. Servers often get
+/// the `Content-Type` of feeds wrong, so the body has the last word.
+pub fn is_html(content_type: Option<&str>, body: &str) -> bool {
+    let start = body
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return true;
+    }
+    if ["<?xml", "<rss", "<feed", "<rdf"]
+        .iter()
+        .any(|xml_start| start.starts_with(xml_start))
+    {
+        return false;
+    }
+
+    match content_type {
+        Some(content_type) => content_type.to_ascii_lowercase().contains("text/html"),
+        None => false,
+    }
+}
+
+/// The URLs of the RSS and Atom feeds a web page links to with
+/// `<link rel="alternate">`, in the order of the page.
+pub fn discover_feeds(html: &str, page_url: &str) -> Vec<String> {
+    let page_url = match Url::parse(page_url) {
+        Ok(page_url) => page_url,
+        Err(_) => return vec![],
+    };
+    let base_url = find_tags(html, "base")
+        .iter()
+        .find_map(|base| base.attribute("href"))
+        .and_then(|href| page_url.join(href.trim()).ok())
+        .unwrap_or(page_url);
+
+    let mut feeds: Vec<String> = vec![];
+
+    for link in find_tags(html, "link") {
+        let alternate = link
+            .attribute("rel")
+            .map(|rel| {
+                rel.split_ascii_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+            })
+            .unwrap_or(false);
+        let feed_type = link
+            .attribute("type")
+            .map(|link_type| {
+                let link_type = link_type.split(';').next().unwrap_or("").trim();
+                FEED_TYPES
+                    .iter()
+                    .any(|feed_type| link_type.eq_ignore_ascii_case(feed_type))
+            })
+            .unwrap_or(false);
+        if !alternate || !feed_type {
+            continue;
+        }
+
+        let feed_url = match link
+            .attribute("href")
+            .filter(|href| !href.trim().is_empty())
+            .and_then(|href| base_url.join(href.trim()).ok())
+        {
+            Some(feed_url) => feed_url.to_string(),
+            None => continue,
+        };
+        if !feeds.contains(&feed_url) {
+            feeds.push(feed_url);
+        }
+    }
+
+    feeds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>blog</title>
+  <!-- <link rel="alternate" type="application/rss+xml" href="/commented.xml"> -->
+  <script>document.write('<link rel="alternate" type="application/rss+xml" href="/script.xml">')</script>
+  <link rel=stylesheet href=/style.css>
+  <LINK REL="alternate" TYPE="application/atom+xml" HREF="/atom.xml" title="Atom &amp; more">
+  <link rel='alternate home' type='application/rss+xml; charset=utf-8' href='https://feeds.example.com/rss'>
+  <link rel="alternate" type="application/rss+xml" href="/atom.xml">
+  <link rel="alternate" type="text/html" hreflang="ja" href="/ja/">
+</head>
+<body><img src="/image.png" alt='a > b'></body>
+</html>"#;
+
+    #[test]
+    fn find_tags_and_attributes() {
+        let links = find_tags(PAGE, "link");
+        assert_eq!(links.len(), 5);
+        assert_eq!(links[0].attribute("rel"), Some("stylesheet"));
+        assert_eq!(links[0].attribute("href"), Some("/style.css"));
+        assert_eq!(links[1].attribute("title"), Some("Atom & more"));
+
+        let images = find_tags(PAGE, "img");
+        assert_eq!(
+            images,
+            vec![HtmlTag {
+                name: "img".to_string(),
+                attributes: vec![
+                    ("src".to_string(), "/image.png".to_string()),
+                    ("alt".to_string(), "a > b".to_string()),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn discover_linked_feeds() {
+        assert_eq!(
+            discover_feeds(PAGE, "https://example.com/blog/"),
+            vec![
+                "https://example.com/atom.xml".to_string(),
+                "https://feeds.example.com/rss".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_feeds_against_base() {
+        let page = r#"<html><head><base href="/blog/"><link rel="alternate" type="application/rss+xml" href="feed.xml"></head></html>"#;
+        assert_eq!(
+            discover_feeds(page, "https://example.com/"),
+            vec!["https://example.com/blog/feed.xml".to_string()]
+        );
+    }
+
+    #[test]
+    fn tell_pages_from_feeds() {
+        assert!(is_html(None, PAGE));
+        assert!(is_html(Some("text/html; charset=utf-8"), "<head></head>"));
+        assert!(!is_html(
+            Some("text/html"),
+            "<?xml version=\"1.0\"?><rss version=\"2.0\"></rss>"
+        ));
+        assert!(!is_html(
+            Some("application/rss+xml"),
+            "<rss version=\"2.0\"></rss>"
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use js_sys::{Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use worker::worker_sys::{Response as EdgeResponse, WorkerGlobalScope};
+use worker::{Error, Headers, Method, RequestInit, RequestRedirect};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    /// The body of a response announcing a larger `Content-Length` is not
+    /// read at all. Any other body is read until it passes this size, and is
+    /// cut there.
+    pub max_body_bytes: Option<usize>,
+    /// When false, redirect responses are returned as they are.
+    pub follow_redirects: bool,
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: vec![],
+            body: None,
+            max_body_bytes: None,
+            follow_redirects: true,
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = Some(max_body_bytes);
+        self
+    }
+
+    pub fn with_follow_redirects(mut self, follow_redirects: bool) -> Self {
+        self.follow_redirects = follow_redirects;
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Sends HTTP requests on behalf of the worker. The worker itself uses
+/// `FetchClient`, tests swap in `mock::MockClient` to serve canned responses.
+#[async_trait(?Send)]
+pub trait HttpClient {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error>;
+}
+
+#[wasm_bindgen]
+extern "C" {
+    type AbortController;
+
+    #[wasm_bindgen(constructor)]
+    fn new() -> AbortController;
+
+    #[wasm_bindgen(method, getter)]
+    fn signal(this: &AbortController) -> JsValue;
+
+    #[wasm_bindgen(method)]
+    fn abort(this: &AbortController);
+
+    /// The `ReadableStreamDefaultReader` of a response body.
+    type BodyReader;
+
+    #[wasm_bindgen(method)]
+    fn read(this: &BodyReader) -> Promise;
+
+    #[wasm_bindgen(method)]
+    fn cancel(this: &BodyReader) -> Promise;
+}
+
+/// Aborts the fetch it guards once dropped, so that a request given up on,
+/// e.g. after a timeout, stops downloading. Aborting a finished fetch does
+/// nothing.
+struct AbortOnDrop(AbortController);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Reads the body of `response` chunk by chunk, and stops reading once it
+/// passes `max_body_bytes`.
+async fn read_body(
+    response: &EdgeResponse,
+    max_body_bytes: Option<usize>,
+) -> Result<String, Error> {
+    let stream = match response.body() {
+        Some(stream) => stream,
+        None => return Ok(String::new()),
+    };
+    let reader: BodyReader = stream.get_reader().unchecked_into();
+
+    let mut bytes: Vec<u8> = vec![];
+    loop {
+        let chunk = JsFuture::from(reader.read()).await?;
+        if Reflect::get(&chunk, &JsValue::from("done"))?.is_truthy() {
+            break;
+        }
+        let value: Uint8Array = Reflect::get(&chunk, &JsValue::from("value"))?.unchecked_into();
+        bytes.extend(value.to_vec());
+
+        if let Some(max_body_bytes) = max_body_bytes {
+            if bytes.len() > max_body_bytes {
+                // The rest of the body is never downloaded
+                let _ = reader.cancel();
+                break;
+            }
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub struct FetchClient;
+
+#[async_trait(?Send)]
+impl HttpClient for FetchClient {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+        let mut request_init = RequestInit::new();
+
+        request_init.with_method(request.method.clone());
+
+        let mut headers = Headers::default();
+
+        for (name, value) in &request.headers {
+            headers.append(name, value)?;
+        }
+
+        request_init.with_headers(headers);
+
+        if !request.follow_redirects {
+            request_init.with_redirect(RequestRedirect::Manual);
+        }
+
+        if let Some(body) = &request.body {
+            request_init.with_body(Some(JsValue::from_str(body)));
+        }
+
+        let abort = AbortOnDrop(AbortController::new());
+        let init = worker_sys_init(&request_init, &abort.0)?;
+
+        let global: WorkerGlobalScope = js_sys::global().unchecked_into();
+        let response = JsFuture::from(global.fetch_with_str_and_init(&request.url, &init)).await?;
+        let response: EdgeResponse = response.dyn_into()?;
+
+        let status = response.status();
+        let headers: Vec<(String, String)> = Headers(response.headers()).entries().collect();
+
+        let content_length = find_header(&headers, "Content-Length")
+            .and_then(|content_length| content_length.parse::<usize>().ok());
+        let body = match (content_length, request.max_body_bytes) {
+            (Some(content_length), Some(max_body_bytes)) if content_length > max_body_bytes => {
+                String::new()
+            }
+            _ => read_body(&response, request.max_body_bytes).await?,
+        };
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// The options of `fetch`, with the signal of `abort`.
+fn worker_sys_init(
+    request_init: &RequestInit,
+    abort: &AbortController,
+) -> Result<worker::worker_sys::RequestInit, Error> {
+    let init = worker::worker_sys::RequestInit::from(request_init);
+    Reflect::set(init.as_ref(), &JsValue::from("signal"), &abort.signal())?;
+    Ok(init)
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Serves canned responses keyed by method and URL, and records every
+    /// request it receives so tests can assert on what was sent.
+    pub struct MockClient {
+        routes: RefCell<Vec<(Method, String, Vec<HttpResponse>)>>,
+        requests: RefCell<Vec<HttpRequest>>,
+    }
+
+    impl MockClient {
+        pub fn new() -> Self {
+            Self {
+                routes: RefCell::new(vec![]),
+                requests: RefCell::new(vec![]),
+            }
+        }
+
+        /// Queues a response for `method` and `url`. Queued responses are
+        /// served in order, and the last one keeps being served once the
+        /// queue is drained.
+        pub fn on(&self, method: Method, url: impl Into<String>, response: HttpResponse) {
+            let url = url.into();
+            let mut routes = self.routes.borrow_mut();
+            match routes
+                .iter_mut()
+                .find(|(route_method, route_url, _)| *route_method == method && *route_url == url)
+            {
+                Some((_, _, responses)) => responses.push(response),
+                None => routes.push((method, url, vec![response])),
+            }
+        }
+
+        pub fn requests(&self) -> Vec<HttpRequest> {
+            self.requests.borrow().clone()
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl HttpClient for MockClient {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Error> {
+            self.requests.borrow_mut().push(request.clone());
+
+            let mut routes = self.routes.borrow_mut();
+            let responses = match routes.iter_mut().find(|(method, url, _)| {
+                *method == request.method && *url == request.url
+            }) {
+                Some((_, _, responses)) => responses,
+                None => {
+                    return Err(Error::RustError(format!(
+                        "no canned response for {:?} {}",
+                        request.method, request.url
+                    )))
+                }
+            };
+
+            if responses.len() > 1 {
+                Ok(responses.remove(0))
+            } else {
+                Ok(responses[0].clone())
+            }
+        }
+    }
+}
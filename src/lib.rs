@@ -3,12 +3,27 @@
 use serde_json;
 use worker::*;
 
-mod latest_pushed_date_memory;
+mod admin;
+mod auth;
+mod categories;
+mod feed_auth;
+mod feed_state_memory;
+mod html;
+mod http;
+mod memory;
 mod notion;
+mod oauth;
+mod opml;
+mod polling;
 mod rss;
+mod rules;
+mod schema;
 mod store;
 mod subscribe;
+mod sync;
+mod tenant;
 mod utils;
+mod websub;
 
 fn log_request(req: &Request) {
     console_log!(
@@ -24,73 +39,55 @@ fn log_request(req: &Request) {
 pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
     log_request(&req);
     utils::set_panic_hook();
-    let router = Router::new();
-    router
-        .get_async("/", |mut req, ctx| async move {
-            let list = subscribe::get_subscribe_list();
-
-            let kv = ctx.kv("LATEST_PUSHED_DATES")?;
-
-            let database_id = ctx.secret("database_id").unwrap().to_string();
-            let notify_user_id = ctx.secret("notify_user_id").unwrap().to_string();
-            let notion_api_key = ctx.secret("notion_api_key").unwrap().to_string();
-
-            let notion_query = notion::NotionCommand::build(database_id, notify_user_id, notion_api_key);
-
-            for xml in list {
-                let rss_url = xml.rss_url.clone();
-                let tags = xml.tags.clone();
-
-                let latest_pushed_date =
-                    latest_pushed_date_memory::get_latest_pushed_date(&kv, &rss_url).await;
-                let rss = match xml.into_rss().await {
-                    Ok(rss) => rss,
-                    Err(err) => return Response::error("internal server error", 500),
-                };
-                let latest_pushed_date_millis = match latest_pushed_date {
-                    Some(latest_pushed_date) => latest_pushed_date.as_millis(),
-                    None => 0,
-                };
-                let latest_pushed_date = rss.exclude_latest_published_date();
-                let items = rss.items.iter().filter(|item| {
-                    let item_published_date = match &item.published_date {
-                        Some(item_published_date) => item_published_date.as_millis(),
-                        None => 0,
-                    };
-                    true
-                });
 
-                let store_columns = items.map(|item| {
-                    store::StoreSchema::new(
-                        &item.id,
-                        &item.blog_title,
-                        &item.article_title,
-                        rss_url.clone(),
-                        tags.clone(),
-                        &item.description,
-                        &item.article_url,
-                        &item.published_date,
-                    )
-                });
+    // Every route but the ones called by third parties starts a sync or
+    // touches the subscriptions, so nothing runs before the caller is
+    // authenticated.
+    if !auth::is_public(&req.path()) {
+        if let Some(response) = auth::authorize(&req, &env)? {
+            return Ok(response);
+        }
+    }
 
-                for store_column in store_columns {
-                    let res = notion_query.insert_column(store_column).await;
-                    match res {
-                        Ok(_) => {},
-                        Err(err) => worker::console_log!("err: {:?}", err)
-                    };
+    let router = Router::new();
+    router
+        .get_async("/", |_req, ctx| async move {
+            match sync::run(&ctx.env).await {
+                Ok(report) => Response::from_json(&report),
+                Err(err) => {
+                    console_log!("failed to sync: {}", err);
+                    Response::error("internal server error", 500)
                 }
-
-                latest_pushed_date_memory::put_latest_pushed_date(
-                    &kv,
-                    &rss_url,
-                    latest_pushed_date,
-                )
-                .await;
             }
-
-            Response::ok("ok")
         })
+        .get_async("/subscriptions", admin::list_subscriptions)
+        .post_async("/subscriptions", admin::post_subscription)
+        .put_async("/subscriptions", admin::put_subscription)
+        .delete_async("/subscriptions", admin::remove_subscription)
+        .post_async("/subscriptions/pause", admin::pause_subscription)
+        .post_async("/subscriptions/resume", admin::resume_subscription)
+        .get_async("/subscriptions/opml", admin::get_opml)
+        .post_async("/subscriptions/opml", admin::post_opml)
+        .get_async("/schema", schema::get_schema)
+        .post_async("/schema", schema::post_schema)
+        .post_async("/schema/database", schema::post_database)
+        .get_async(auth::WEBSUB_CALLBACK_PATH, websub::verify_callback)
+        .post_async(auth::WEBSUB_CALLBACK_PATH, websub::receive_callback)
+        .get_async("/oauth/authorize", oauth::authorize)
+        .get_async(auth::OAUTH_CALLBACK_PATH, oauth::callback)
         .run(req, env)
         .await
 }
+
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+    match sync::run(&env).await {
+        Ok(report) => console_log!(
+            "{} - synced: {}",
+            Date::now().to_string(),
+            serde_json::to_string(&report).unwrap_or_default()
+        ),
+        Err(err) => console_log!("{} - failed to sync: {}", Date::now().to_string(), err),
+    }
+}
@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use worker::{kv::KvStore, Error};
+
+/// Key-value storage used to remember state between runs. The worker keeps
+/// it in a KV namespace, tests keep it in `mock::MemoryStore`.
+#[async_trait(?Send)]
+pub trait Memory {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+    async fn put(&self, key: &str, value: &str) -> Result<(), Error>;
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+#[async_trait(?Send)]
+impl Memory for KvStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        match self.get(key).text().await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    async fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        let put_command = match self.put(key, value.to_string()) {
+            Ok(cmd) => cmd,
+            Err(err) => return Err(Error::from(err)),
+        };
+
+        match put_command.execute().await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match self.delete(key).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+}
+
+/// Prepends `prefix` to every key of `memory`, so that tenants sharing a KV
+/// namespace never see the keys of each other.
+pub struct NamespacedMemory<'a, M: Memory> {
+    memory: &'a M,
+    prefix: String,
+}
+
+impl<'a, M: Memory> NamespacedMemory<'a, M> {
+    pub fn new(memory: &'a M, prefix: impl Into<String>) -> Self {
+        Self {
+            memory,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a, M: Memory> Memory for NamespacedMemory<'a, M> {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        self.memory.get(&self.key(key)).await
+    }
+
+    async fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.memory.put(&self.key(key), value).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.memory.delete(&self.key(key)).await
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    pub struct MemoryStore {
+        values: RefCell<BTreeMap<String, String>>,
+    }
+
+    impl MemoryStore {
+        pub fn new() -> Self {
+            Self {
+                values: RefCell::new(BTreeMap::new()),
+            }
+        }
+
+        pub fn value(&self, key: &str) -> Option<String> {
+            self.values.borrow().get(key).cloned()
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl Memory for MemoryStore {
+        async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+            Ok(self.value(key))
+        }
+
+        async fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+            self.values
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.values.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MemoryStore;
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    async fn prefix_keys() {
+        let store = MemoryStore::new();
+        let alice = NamespacedMemory::new(&store, "tenant:alice:");
+        let bob = NamespacedMemory::new(&store, "tenant:bob:");
+
+        alice.put("subscriptions", "[]").await.unwrap();
+        assert_eq!(
+            store.value("tenant:alice:subscriptions"),
+            Some("[]".to_string())
+        );
+        assert_eq!(
+            alice.get("subscriptions").await.unwrap(),
+            Some("[]".to_string())
+        );
+        assert_eq!(bob.get("subscriptions").await.unwrap(), None);
+        assert_eq!(store.value("subscriptions"), None);
+
+        alice.delete("subscriptions").await.unwrap();
+        assert_eq!(store.value("tenant:alice:subscriptions"), None);
+    }
+}
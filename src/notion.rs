@@ -1,21 +1,126 @@
+use crate::categories::CategoryOptions;
+use crate::http::{HttpClient, HttpRequest, HttpResponse};
+use crate::rss::Enclosure;
+use crate::schema;
 use crate::store::StoreSchema;
-use serde::Serialize;
-use wasm_bindgen::JsValue;
-use worker::{Date, Error, Fetch, Headers, Method, Request, RequestInit};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use worker::{Date, Env, Error, Method, Url};
 
 const api_url_create_page: &str = "https://api.notion.com/v1/pages";
-const api_version: &str = "2022-02-22";
+pub(crate) const api_version: &str = "2022-02-22";
+
+/// The extensions of the audio files Notion can embed.
+const AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "wav", "ogg", "oga", "m4a"];
+
+/// Notion allows an average of three requests per second per integration.
+pub const DEFAULT_REQUEST_INTERVAL_MILLIS: u64 = 334;
 
 #[derive(Debug)]
 pub enum NotionCommandError {
     WorkerError(Error),
     SerializeError(serde_json::Error),
+    UnknownTarget(String),
+    /// Notion answered with an error, e.g. 400 when the database lacks a
+    /// property of the page.
+    ApiError {
+        status: u16,
+        body: String,
+    },
+}
+
+/// Renames the properties pages are created with, e.g.
+/// `{ "blog_name": "Source", "guid": null }`. Properties keep their name when
+/// they are not listed, and are not sent when they are mapped to `null`. A
+/// mapping giving two properties the same name is rejected, as one would
+/// overwrite the other in every page.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "HashMap<String, Option<String>>")]
+pub struct PropertyMapping(HashMap<String, Option<String>>);
+
+impl TryFrom<HashMap<String, Option<String>>> for PropertyMapping {
+    type Error = String;
+
+    fn try_from(mapping: HashMap<String, Option<String>>) -> std::result::Result<Self, String> {
+        let mapping = Self(mapping);
+
+        let mut names = HashSet::new();
+        for (property, _, _) in schema::PROPERTIES.iter() {
+            if let Some(name) = mapping.name(property) {
+                if !names.insert(name.clone()) {
+                    return Err(format!("more than one property is named {}", name));
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+impl PropertyMapping {
+    /// The mapping of the default database, read as JSON from the
+    /// `NOTION_PROPERTIES` variable.
+    pub fn from_env(env: &Env) -> Self {
+        match read_json_var(env, "NOTION_PROPERTIES") {
+            Some(mapping) => mapping,
+            None => Self::default(),
+        }
+    }
+
+    /// The name `property` has in the database, `None` when it is not sent.
+    pub fn name(&self, property: &str) -> Option<String> {
+        match self.0.get(property) {
+            Some(name) => name.clone(),
+            None => Some(property.to_string()),
+        }
+    }
+}
+
+/// A database items can be routed to instead of the default one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotionTarget {
+    pub database_id: String,
+    #[serde(default)]
+    pub properties: PropertyMapping,
+}
+
+/// The databases items can be routed to by name, read as JSON from the
+/// `NOTION_TARGETS` variable.
+pub fn targets_from_env(env: &Env) -> HashMap<String, NotionTarget> {
+    match read_json_var(env, "NOTION_TARGETS") {
+        Some(targets) => targets,
+        None => HashMap::new(),
+    }
+}
+
+fn read_json_var<T: for<'de> Deserialize<'de>>(env: &Env, name: &str) -> Option<T> {
+    let value = match env.var(name) {
+        Ok(value) if !value.to_string().trim().is_empty() => value.to_string(),
+        _ => return None,
+    };
+
+    match serde_json::from_str(&value) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            worker::console_log!("{} is not valid and is ignored: {}", name, err);
+            None
+        }
+    }
 }
 
 pub struct NotionCommand {
     pub database_id: String,
     pub notify_user_id: String,
     pub notion_api_key: String,
+    request_interval_millis: u64,
+    last_requested_at: Cell<Option<u64>>,
+    categories: Option<CategoryOptions>,
+    podcasts: bool,
+    properties: PropertyMapping,
+    targets: HashMap<String, NotionTarget>,
 }
 
 impl NotionCommand {
@@ -24,58 +129,224 @@ impl NotionCommand {
             database_id,
             notify_user_id,
             notion_api_key,
+            request_interval_millis: 0,
+            last_requested_at: Cell::new(None),
+            categories: None,
+            podcasts: false,
+            properties: PropertyMapping::default(),
+            targets: HashMap::new(),
         }
     }
 
-    pub async fn insert_column(&self, column: StoreSchema) -> Result<(), NotionCommandError> {
-        let mut request_init = RequestInit::new();
+    /// Spaces the requests sent through this command by at least
+    /// `request_interval_millis`, so that they stay under the Notion rate limit.
+    pub fn with_request_interval(mut self, request_interval_millis: u64) -> Self {
+        self.request_interval_millis = request_interval_millis;
+        self
+    }
+
+    /// Syncs the categories of items to the `categories` property, normalized
+    /// with `categories`. They are left out when it is `None`.
+    pub fn with_categories(mut self, categories: Option<CategoryOptions>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Syncs the `audio_url`, `duration` and `episode` of podcast episodes.
+    /// They are left out when false, so databases without them keep working.
+    pub fn with_podcasts(mut self, podcasts: bool) -> Self {
+        self.podcasts = podcasts;
+        self
+    }
+
+    /// Renames the properties of pages created in `database_id`.
+    pub fn with_properties(mut self, properties: PropertyMapping) -> Self {
+        self.properties = properties;
+        self
+    }
 
-        request_init.with_method(Method::Post);
+    /// The databases columns with a `target` are created in.
+    pub fn with_targets(mut self, targets: HashMap<String, NotionTarget>) -> Self {
+        self.targets = targets;
+        self
+    }
 
-        let mut headers = Headers::default();
+    /// The databases pages are created in with the mapping of their
+    /// properties, the default database first, then every target by name.
+    pub fn databases(&self) -> Vec<(Option<String>, String, PropertyMapping)> {
+        let mut targets: Vec<(&String, &NotionTarget)> = self.targets.iter().collect();
+        targets.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        headers.append("Content-Type", "application/json").unwrap();
-        headers.append("Authorization", &format!("Bearer {}", self.notion_api_key)).unwrap();
-        headers.append("Notion-Version", api_version).unwrap();
+        let mut databases = vec![(None, self.database_id.clone(), self.properties.clone())];
+        for (name, target) in targets {
+            databases.push((
+                Some(name.clone()),
+                target.database_id.clone(),
+                target.properties.clone(),
+            ));
+        }
+        databases
+    }
 
-        request_init.with_headers(headers);
+    pub fn syncs_categories(&self) -> bool {
+        self.categories.is_some()
+    }
 
-        let notion_query = NotionQuery::from_store_schema(column, self.database_id.clone());
+    pub fn syncs_podcasts(&self) -> bool {
+        self.podcasts
+    }
 
-        let mut notion_query = match serde_json::to_string(&notion_query) {
+    async fn wait_for_turn(&self) {
+        if let Some(last_requested_at) = self.last_requested_at.get() {
+            let elapsed = Date::now().as_millis().saturating_sub(last_requested_at);
+            if elapsed < self.request_interval_millis {
+                utils::sleep(self.request_interval_millis - elapsed).await;
+            }
+        }
+        self.last_requested_at.set(Some(Date::now().as_millis()));
+    }
+
+    pub async fn insert_column(
+        &self,
+        client: &impl HttpClient,
+        mut column: StoreSchema,
+    ) -> Result<(), NotionCommandError> {
+        column.categories = match &self.categories {
+            Some(categories) => categories.normalize(&column.categories),
+            None => vec![],
+        };
+        let (database_id, properties) = match &column.target {
+            Some(target) => match self.targets.get(target) {
+                Some(target) => (target.database_id.clone(), &target.properties),
+                None => return Err(NotionCommandError::UnknownTarget(target.clone())),
+            },
+            None => (self.database_id.clone(), &self.properties),
+        };
+        let notion_query =
+            NotionQuery::from_store_schema(column, database_id, properties, self.podcasts);
+
+        self.create_page(client, &notion_query).await
+    }
+
+    /// Creates a page telling `notify_user_id` that `rss_url` was disabled
+    /// because it kept failing.
+    pub async fn notify_broken_feed(
+        &self,
+        client: &impl HttpClient,
+        rss_url: &str,
+        error: &str,
+    ) -> Result<(), NotionCommandError> {
+        let column = StoreSchema::new(
+            "",
+            "rss-stream-to-notion",
+            format!("Feed is broken: {}", rss_url),
+            rss_url,
+            vec!["broken feed".to_string()],
+            error,
+            &Some(rss_url.to_string()),
+            &None,
+        );
+
+        let mut notion_query = NotionQuery::from_store_schema(
+            column,
+            self.database_id.clone(),
+            &self.properties,
+            false,
+        );
+        notion_query.children.push(NotionBlockQuery {
+            object: "block".to_string(),
+            __WILL_BE_REPLACED__type: "paragraph".to_string(),
+            paragraph: Some(NotionParagraphQuery {
+                rich_text: vec![
+                    NotionInlineQuery::Mention(NotionMentionQuery {
+                        __WILL_BE_REPLACED__type: "mention".to_string(),
+                        mention: NotionUserMentionQuery {
+                            __WILL_BE_REPLACED__type: "user".to_string(),
+                            user: NotionUserQuery {
+                                id: self.notify_user_id.clone(),
+                            },
+                        },
+                    }),
+                    NotionInlineQuery::Text(NotionTextQuery {
+                        __WILL_BE_REPLACED__type: "text".to_string(),
+                        text: NotionContentQuery {
+                            content: format!(
+                                " {} kept failing and is not synced anymore until it is resumed. The last error was: {}",
+                                rss_url, error
+                            ),
+                        },
+                    }),
+                ],
+            }),
+            audio: None,
+        });
+
+        self.create_page(client, &notion_query).await
+    }
+
+    async fn create_page(
+        &self,
+        client: &impl HttpClient,
+        notion_query: &NotionQuery,
+    ) -> Result<(), NotionCommandError> {
+        let mut notion_query = match serde_json::to_string(notion_query) {
             Ok(query) => query,
             // TODO(#1) Inherite error information to log more detailed error
             Err(err) => return Err(NotionCommandError::SerializeError(err)),
         };
         notion_query.remove_matches("__WILL_BE_REPLACED__");
 
-        request_init.with_body(Some(JsValue::from_str(&notion_query)));
+        match self
+            .request(
+                client,
+                Method::Post,
+                api_url_create_page,
+                Some(notion_query),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
 
-        let request = match Request::new_with_init(&api_url_create_page, &request_init) {
-            Ok(request) => request,
-            Err(err) => {
-                // TODO(#1) Inherite error information to log more detailed error
-                return Err(NotionCommandError::WorkerError(err));
-            }
-        };
+    /// Sends a request to the Notion API with the token of this command,
+    /// through its rate limit. Error statuses are returned as `ApiError`.
+    pub async fn request(
+        &self,
+        client: &impl HttpClient,
+        method: Method,
+        url: &str,
+        body: Option<String>,
+    ) -> Result<HttpResponse, NotionCommandError> {
+        let mut request = HttpRequest::new(method, url);
+        if body.is_some() {
+            request = request.with_header("Content-Type", "application/json");
+        }
+        request = request
+            .with_header("Authorization", format!("Bearer {}", self.notion_api_key))
+            .with_header("Notion-Version", api_version);
+        if let Some(body) = body {
+            request = request.with_body(body);
+        }
 
-        let mut response = match Fetch::Request(request).send().await {
-            Ok(response) => response,
-            Err(err) => {
-                // TODO(#1) Inherite error information to log more detailed error
-                return Err(NotionCommandError::WorkerError(err));
-            }
-        };
+        self.wait_for_turn().await;
 
-        let text = match response.text().await {
-            Ok(text) => text,
+        let response = match client.send(request).await {
+            Ok(response) => response,
             Err(err) => {
                 // TODO(#1) Inherite error information to log more detailed error
                 return Err(NotionCommandError::WorkerError(err));
             }
         };
 
-        Ok(())
+        if !(200..300).contains(&response.status) {
+            return Err(NotionCommandError::ApiError {
+                status: response.status,
+                body: response.body,
+            });
+        }
+        Ok(response)
     }
 }
 
@@ -128,6 +399,11 @@ struct NotionSelectQuery {
     name: String,
 }
 
+#[derive(Serialize)]
+struct NotionNumberQuery {
+    number: u64,
+}
+
 #[derive(Serialize)]
 struct NotionMultiSelectQuery {
     multi_select: Vec<NotionSelectQuery>,
@@ -141,40 +417,153 @@ struct NotionChildQuery {
 }
 
 #[derive(Serialize)]
-struct NotionPropertiesQuery {
-    blog_name: NotionRichTextQuery,
-    article_title: NotionTitleQuery,
-    tags: NotionMultiSelectQuery,
-    guid: NotionRichTextQuery,
-    description: NotionRichTextQuery,
+struct NotionUserQuery {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct NotionUserMentionQuery {
+    __WILL_BE_REPLACED__type: String,
+    user: NotionUserQuery,
+}
+
+#[derive(Serialize)]
+struct NotionMentionQuery {
+    __WILL_BE_REPLACED__type: String,
+    mention: NotionUserMentionQuery,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum NotionInlineQuery {
+    Text(NotionTextQuery),
+    Mention(NotionMentionQuery),
+}
+
+#[derive(Serialize)]
+struct NotionParagraphQuery {
+    rich_text: Vec<NotionInlineQuery>,
+}
+
+#[derive(Serialize)]
+struct NotionExternalQuery {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct NotionFileQuery {
+    __WILL_BE_REPLACED__type: String,
+    external: NotionExternalQuery,
+}
+
+impl NotionFileQuery {
+    fn external(url: String) -> Self {
+        Self {
+            __WILL_BE_REPLACED__type: "external".to_string(),
+            external: NotionExternalQuery { url },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NotionBlockQuery {
+    object: String,
+    __WILL_BE_REPLACED__type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    link: Option<NotionUrlQuery>,
+    paragraph: Option<NotionParagraphQuery>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    published_date: Option<NotionDateQuery>,
+    audio: Option<NotionFileQuery>,
+}
+
+impl NotionBlockQuery {
+    /// An audio player for `enclosure`, if Notion can play it.
+    fn audio(enclosure: &Enclosure) -> Option<Self> {
+        let url = match Url::parse(&enclosure.url) {
+            Ok(url) => url,
+            Err(_) => return None,
+        };
+        let extension = match url.path().rsplit_once('.') {
+            Some((_, extension)) => extension.to_ascii_lowercase(),
+            None => return None,
+        };
+        if !AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+
+        Some(Self {
+            object: "block".to_string(),
+            __WILL_BE_REPLACED__type: "audio".to_string(),
+            paragraph: None,
+            audio: Some(NotionFileQuery::external(enclosure.url.clone())),
+        })
+    }
+}
+
+/// A property of a page, serialized as the value Notion expects for its
+/// type.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum NotionPropertyQuery {
+    Title(NotionTitleQuery),
+    RichText(NotionRichTextQuery),
+    MultiSelect(NotionMultiSelectQuery),
+    Url(NotionUrlQuery),
+    Date(NotionDateQuery),
+    Number(NotionNumberQuery),
+}
+
+impl NotionPropertyQuery {
+    fn rich_text(content: String) -> Self {
+        Self::RichText(NotionRichTextQuery {
+            rich_text: vec![NotionTextQuery {
+                __WILL_BE_REPLACED__type: "text".to_string(),
+                text: NotionContentQuery { content },
+            }],
+            __WILL_BE_REPLACED__type: "rich_text".to_string(),
+        })
+    }
+
+    fn multi_select(names: &[String]) -> Self {
+        Self::MultiSelect(NotionMultiSelectQuery {
+            multi_select: names
+                .iter()
+                .map(|name| NotionSelectQuery {
+                    name: name.to_string(),
+                })
+                .collect(),
+        })
+    }
 }
 
 #[derive(Serialize)]
 struct NotionQuery {
     parent: NotionParentQuery,
-    properties: NotionPropertiesQuery,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover: Option<NotionFileQuery>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<NotionFileQuery>,
+    /// Keyed by the names of the properties in the database.
+    properties: BTreeMap<String, NotionPropertyQuery>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<NotionBlockQuery>,
 }
 
 impl NotionQuery {
-    pub fn from_store_schema(store_schema: StoreSchema, database_id: String) -> Self {
-        let multi_select: Vec<NotionSelectQuery> = store_schema
-            .tags
-            .iter()
-            .map(|tag| NotionSelectQuery {
-                name: tag.to_string(),
-            })
-            .collect();
-        Self {
-            parent: NotionParentQuery {
-                __WILL_BE_REPLACED__type: "database_id".to_string(),
-                database_id,
-            },
-            properties: NotionPropertiesQuery {
-                article_title: NotionTitleQuery {
+    pub fn from_store_schema(
+        store_schema: StoreSchema,
+        database_id: String,
+        mapping: &PropertyMapping,
+        podcasts: bool,
+    ) -> Self {
+        let children = match &store_schema.media.enclosure {
+            Some(enclosure) => NotionBlockQuery::audio(enclosure).into_iter().collect(),
+            None => vec![],
+        };
+
+        let mut properties = vec![
+            (
+                "article_title",
+                NotionPropertyQuery::Title(NotionTitleQuery {
                     title: vec![NotionTextQuery {
                         __WILL_BE_REPLACED__type: "text".to_string(),
                         text: NotionContentQuery {
@@ -182,48 +571,80 @@ impl NotionQuery {
                         },
                     }],
                     __WILL_BE_REPLACED__type: "title".to_string(),
-                },
-                blog_name: NotionRichTextQuery {
-                    rich_text: vec![NotionTextQuery {
-                        __WILL_BE_REPLACED__type: "text".to_string(),
-                        text: NotionContentQuery {
-                            content: store_schema.blog_title,
-                        },
-                    }],
-                    __WILL_BE_REPLACED__type: "rich_text".to_string(),
-                },
-                tags: NotionMultiSelectQuery { multi_select },
-                guid: NotionRichTextQuery {
-                    rich_text: vec![NotionTextQuery {
-                        __WILL_BE_REPLACED__type: "text".to_string(),
-                        text: NotionContentQuery {
-                            content: store_schema.guid,
-                        },
-                    }],
-                    __WILL_BE_REPLACED__type: "rich_text".to_string(),
-                },
-                description: NotionRichTextQuery {
-                    rich_text: vec![NotionTextQuery {
-                        __WILL_BE_REPLACED__type: "text".to_string(),
-                        text: NotionContentQuery {
-                            content: store_schema.description.clone(),
-                        },
-                    }],
-                    __WILL_BE_REPLACED__type: "rich_text".to_string(),
-                },
-                link: match store_schema.link {
-                    Some(link) => Some(NotionUrlQuery { url: link }),
-                    None => None,
-                },
-                published_date: match store_schema.published_date {
-                    Some(published_date) => Some(NotionDateQuery {
-                        date: NotionStartDateQuery {
-                            start: published_date.to_string(),
-                        },
+                }),
+            ),
+            (
+                "blog_name",
+                NotionPropertyQuery::rich_text(store_schema.blog_title),
+            ),
+            (
+                "tags",
+                NotionPropertyQuery::multi_select(&store_schema.tags),
+            ),
+            ("guid", NotionPropertyQuery::rich_text(store_schema.guid)),
+            (
+                "description",
+                NotionPropertyQuery::rich_text(store_schema.description),
+            ),
+        ];
+        if !store_schema.categories.is_empty() {
+            properties.push((
+                "categories",
+                NotionPropertyQuery::multi_select(&store_schema.categories),
+            ));
+        }
+        if let Some(link) = store_schema.link {
+            properties.push((
+                "link",
+                NotionPropertyQuery::Url(NotionUrlQuery { url: link }),
+            ));
+        }
+        if let Some(published_date) = store_schema.published_date {
+            properties.push((
+                "published_date",
+                NotionPropertyQuery::Date(NotionDateQuery {
+                    date: NotionStartDateQuery {
+                        start: published_date.to_string(),
+                    },
+                }),
+            ));
+        }
+        if podcasts {
+            // Blogs attach PDFs or videos too, only audio makes an episode
+            if let Some(enclosure) = store_schema.media.enclosure.filter(Enclosure::is_audio) {
+                properties.push((
+                    "audio_url",
+                    NotionPropertyQuery::Url(NotionUrlQuery { url: enclosure.url }),
+                ));
+            }
+            if let Some(duration_seconds) = store_schema.media.duration_seconds {
+                properties.push((
+                    "duration",
+                    NotionPropertyQuery::Number(NotionNumberQuery {
+                        number: duration_seconds,
                     }),
-                    None => None,
-                },
+                ));
+            }
+            if let Some(episode) = store_schema.media.episode {
+                properties.push((
+                    "episode",
+                    NotionPropertyQuery::Number(NotionNumberQuery { number: episode }),
+                ));
+            }
+        }
+
+        Self {
+            parent: NotionParentQuery {
+                __WILL_BE_REPLACED__type: "database_id".to_string(),
+                database_id,
             },
+            cover: store_schema.cover.map(NotionFileQuery::external),
+            icon: store_schema.icon.map(NotionFileQuery::external),
+            properties: properties
+                .into_iter()
+                .filter_map(|(property, query)| mapping.name(property).map(|name| (name, query)))
+                .collect(),
+            children,
         }
     }
 }
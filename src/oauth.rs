@@ -0,0 +1,768 @@
+use crate::auth;
+use crate::http::{FetchClient, HttpClient, HttpRequest};
+use crate::memory::Memory;
+use crate::notion;
+use crate::tenant::{self, Tenant};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use worker::{Date, Env, Error, Method, Request, Response, Result, RouteContext, Url};
+
+const DEFAULT_AUTHORIZE_URL: &str = "https://api.notion.com/v1/oauth/authorize";
+const DEFAULT_TOKEN_URL: &str = "https://api.notion.com/v1/oauth/token";
+const SEARCH_URL: &str = "https://api.notion.com/v1/search";
+
+/// How long the user has to go through the Notion consent screen.
+const STATE_TTL_MILLIS: u64 = 60 * 60 * 1000;
+const STATE_LEN: usize = 32;
+
+const STATE_KEY_PREFIX: &str = "oauth:state:";
+const INSTALLATION_KEY_PREFIX: &str = "oauth:installation:";
+/// The users who connected their workspace, since KV cannot be listed
+/// through `Memory`.
+const INSTALLATIONS_KEY: &str = "oauth:installations";
+
+pub struct OAuthOptions {
+    pub client_id: String,
+    pub client_secret: String,
+    /// The public URL of the worker, Notion sends users back to its
+    /// `/oauth/callback` route.
+    pub base_url: String,
+    pub authorize_url: String,
+    pub token_url: String,
+}
+
+impl OAuthOptions {
+    /// OAuth is only enabled when the `NOTION_OAUTH_CLIENT_ID` and
+    /// `OAUTH_BASE_URL` variables and the `notion_oauth_client_secret` secret
+    /// are set. `NOTION_OAUTH_AUTHORIZE_URL` and `NOTION_OAUTH_TOKEN_URL`
+    /// point at another provider than Notion.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let var = |name: &str| match env.var(name) {
+            Ok(var) if !var.to_string().trim().is_empty() => Some(var.to_string()),
+            _ => None,
+        };
+
+        let client_id = var("NOTION_OAUTH_CLIENT_ID")?;
+        let base_url = var("OAUTH_BASE_URL")?;
+        let client_secret = match env.secret("notion_oauth_client_secret") {
+            Ok(secret) if !secret.to_string().is_empty() => secret.to_string(),
+            _ => {
+                worker::console_log!(
+                    "notion_oauth_client_secret secret is not set, OAuth is disabled"
+                );
+                return None;
+            }
+        };
+
+        Some(Self {
+            client_id,
+            client_secret,
+            base_url,
+            authorize_url: var("NOTION_OAUTH_AUTHORIZE_URL")
+                .unwrap_or_else(|| DEFAULT_AUTHORIZE_URL.to_string()),
+            token_url: var("NOTION_OAUTH_TOKEN_URL")
+                .unwrap_or_else(|| DEFAULT_TOKEN_URL.to_string()),
+        })
+    }
+
+    fn redirect_uri(&self) -> String {
+        format!(
+            "{}{}",
+            self.base_url.trim_end_matches('/'),
+            auth::OAUTH_CALLBACK_PATH
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum OAuthError {
+    Invalid(String),
+    /// The state is unknown, used or expired, the authorization has to start
+    /// over.
+    State,
+    /// The user did not grant access.
+    Denied(String),
+    /// The token endpoint did not hand out a token for the code.
+    Exchange(String),
+    /// No database to sync to was chosen.
+    NoDatabase(String),
+    WorkerError(Error),
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuthError::Invalid(message) => write!(f, "{}", message),
+            OAuthError::State => write!(f, "authorization expired, please start again"),
+            OAuthError::Denied(error) => write!(f, "authorization was denied: {}", error),
+            OAuthError::Exchange(message) => write!(f, "failed to get a token: {}", message),
+            OAuthError::NoDatabase(message) => write!(f, "{}", message),
+            OAuthError::WorkerError(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl OAuthError {
+    fn status(&self) -> u16 {
+        match self {
+            OAuthError::Invalid(_) => 400,
+            OAuthError::State => 400,
+            OAuthError::Denied(_) => 403,
+            OAuthError::Exchange(_) => 502,
+            OAuthError::NoDatabase(_) => 400,
+            OAuthError::WorkerError(_) => 500,
+        }
+    }
+
+    fn into_response(self) -> Result<Response> {
+        Response::error(self.to_string(), self.status())
+    }
+}
+
+/// An authorization which was started but did not come back yet, stored
+/// under its state.
+#[derive(Serialize, Deserialize)]
+struct PendingAuthorization {
+    user: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    database_id: Option<String>,
+    expires_at: u64,
+}
+
+/// A workspace connected through OAuth, stored per user. It is deliberately
+/// not `Debug` so the token cannot end up in a log line.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Installation {
+    pub user: String,
+    pub access_token: String,
+    pub database_id: String,
+    /// The Notion user who installed the integration, mentioned when one of
+    /// their feeds breaks.
+    #[serde(default)]
+    pub notify_user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_id: Option<String>,
+}
+
+impl Installation {
+    pub fn tenant(&self) -> Tenant {
+        Tenant {
+            id: self.user.clone(),
+            database_id: self.database_id.clone(),
+            notify_user_id: self.notify_user_id.clone(),
+            notion_api_key_secret: String::new(),
+            access_token: Some(self.access_token.clone()),
+            properties: Default::default(),
+            targets: HashMap::new(),
+            routes: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    bot_id: Option<String>,
+    #[serde(default)]
+    workspace_name: Option<String>,
+    #[serde(default)]
+    owner: Option<TokenOwner>,
+    /// The database the user duplicated from the template of the
+    /// integration, if any.
+    #[serde(default)]
+    duplicated_template_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenOwner {
+    #[serde(default)]
+    user: Option<TokenOwnerUser>,
+}
+
+#[derive(Deserialize)]
+struct TokenOwnerUser {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    id: String,
+}
+
+/// Remembers that `user` is connecting their workspace, and returns the URL
+/// of the consent screen to send them to. `database_id` is the database
+/// their items go to, it is otherwise picked from what they share. The user
+/// becomes a tenant, whose feeds can only read the secrets under
+/// `Tenant::secret_prefix`, whatever the id.
+pub async fn start_authorization(
+    memory: &impl Memory,
+    options: &OAuthOptions,
+    user: &str,
+    database_id: Option<String>,
+    now: u64,
+) -> std::result::Result<String, OAuthError> {
+    if let Err(message) = tenant::validate_id(user) {
+        return Err(OAuthError::Invalid(message));
+    }
+
+    let mut authorize_url = match Url::parse(&options.authorize_url) {
+        Ok(authorize_url) => authorize_url,
+        Err(err) => {
+            return Err(OAuthError::Invalid(format!(
+                "authorize URL is not valid: {}",
+                err
+            )))
+        }
+    };
+
+    let state: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(STATE_LEN)
+        .map(char::from)
+        .collect();
+    let pending = PendingAuthorization {
+        user: user.to_string(),
+        database_id,
+        expires_at: now + STATE_TTL_MILLIS,
+    };
+    let pending = match serde_json::to_string(&pending) {
+        Ok(pending) => pending,
+        Err(err) => return Err(OAuthError::Invalid(err.to_string())),
+    };
+    match memory
+        .put(&format!("{}{}", STATE_KEY_PREFIX, state), &pending)
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => return Err(OAuthError::WorkerError(err)),
+    };
+
+    authorize_url
+        .query_pairs_mut()
+        .append_pair("client_id", &options.client_id)
+        .append_pair("response_type", "code")
+        .append_pair("owner", "user")
+        .append_pair("redirect_uri", &options.redirect_uri())
+        .append_pair("state", &state);
+    Ok(authorize_url.to_string())
+}
+
+/// Exchanges the `code` the user came back with for a token, and stores it
+/// with the chosen database as the installation of the user who started the
+/// authorization. A state can only be used once.
+pub async fn complete_authorization(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    options: &OAuthOptions,
+    code: &str,
+    state: &str,
+    now: u64,
+) -> std::result::Result<Installation, OAuthError> {
+    let key = format!("{}{}", STATE_KEY_PREFIX, state);
+    let pending = match memory.get(&key).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => return Err(OAuthError::State),
+        Err(err) => return Err(OAuthError::WorkerError(err)),
+    };
+    match memory.delete(&key).await {
+        Ok(_) => {}
+        Err(err) => return Err(OAuthError::WorkerError(err)),
+    };
+    let pending: PendingAuthorization = match serde_json::from_str(&pending) {
+        Ok(pending) => pending,
+        Err(_) => return Err(OAuthError::State),
+    };
+    if pending.expires_at <= now {
+        return Err(OAuthError::State);
+    }
+
+    let request = HttpRequest::new(Method::Post, &options.token_url)
+        .with_header("Content-Type", "application/json")
+        .with_header(
+            "Authorization",
+            format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", options.client_id, options.client_secret))
+            ),
+        )
+        .with_body(
+            json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": options.redirect_uri(),
+            })
+            .to_string(),
+        );
+    let response = match client.send(request).await {
+        Ok(response) => response,
+        Err(err) => return Err(OAuthError::Exchange(err.to_string())),
+    };
+    if response.status != 200 {
+        return Err(OAuthError::Exchange(format!(
+            "token endpoint answered {}: {}",
+            response.status, response.body
+        )));
+    }
+    let token: TokenResponse = match serde_json::from_str(&response.body) {
+        Ok(token) => token,
+        Err(err) => return Err(OAuthError::Exchange(err.to_string())),
+    };
+
+    let database_id = match pending.database_id.or(token.duplicated_template_id) {
+        Some(database_id) => database_id,
+        None => find_database(client, &token.access_token).await?,
+    };
+
+    let installation = Installation {
+        user: pending.user,
+        access_token: token.access_token,
+        database_id,
+        notify_user_id: token
+            .owner
+            .and_then(|owner| owner.user)
+            .map(|user| user.id)
+            .unwrap_or_default(),
+        workspace_name: token.workspace_name,
+        bot_id: token.bot_id,
+    };
+    save_installation(memory, &installation).await?;
+
+    Ok(installation)
+}
+
+/// The database the user shared with the integration on the consent
+/// screen, when they shared exactly one.
+async fn find_database(
+    client: &impl HttpClient,
+    access_token: &str,
+) -> std::result::Result<String, OAuthError> {
+    let request = HttpRequest::new(Method::Post, SEARCH_URL)
+        .with_header("Content-Type", "application/json")
+        .with_header("Authorization", format!("Bearer {}", access_token))
+        .with_header("Notion-Version", notion::api_version)
+        .with_body(json!({ "filter": { "property": "object", "value": "database" } }).to_string());
+    let response = match client.send(request).await {
+        Ok(response) if response.status == 200 => response,
+        Ok(response) => {
+            return Err(OAuthError::Exchange(format!(
+                "search answered {}: {}",
+                response.status, response.body
+            )))
+        }
+        Err(err) => return Err(OAuthError::Exchange(err.to_string())),
+    };
+    let search: SearchResponse = match serde_json::from_str(&response.body) {
+        Ok(search) => search,
+        Err(err) => return Err(OAuthError::Exchange(err.to_string())),
+    };
+
+    match search.results.as_slice() {
+        [database] => Ok(database.id.clone()),
+        [] => Err(OAuthError::NoDatabase(
+            "no database was shared with the integration, please start again and share one"
+                .to_string(),
+        )),
+        databases => Err(OAuthError::NoDatabase(format!(
+            "{} databases were shared with the integration, please start again with a database_id",
+            databases.len()
+        ))),
+    }
+}
+
+async fn save_installation(
+    memory: &impl Memory,
+    installation: &Installation,
+) -> std::result::Result<(), OAuthError> {
+    let json = match serde_json::to_string(installation) {
+        Ok(json) => json,
+        Err(err) => return Err(OAuthError::Invalid(err.to_string())),
+    };
+    match memory
+        .put(
+            &format!("{}{}", INSTALLATION_KEY_PREFIX, installation.user),
+            &json,
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => return Err(OAuthError::WorkerError(err)),
+    };
+
+    let mut users = installed_users(memory).await;
+    if users.contains(&installation.user) {
+        return Ok(());
+    }
+    users.push(installation.user.clone());
+    let users = match serde_json::to_string(&users) {
+        Ok(users) => users,
+        Err(err) => return Err(OAuthError::Invalid(err.to_string())),
+    };
+    match memory.put(INSTALLATIONS_KEY, &users).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(OAuthError::WorkerError(err)),
+    }
+}
+
+async fn installed_users(memory: &impl Memory) -> Vec<String> {
+    match memory.get(INSTALLATIONS_KEY).await {
+        Ok(Some(users)) => match serde_json::from_str(&users) {
+            Ok(users) => users,
+            Err(err) => {
+                worker::console_log!("{} is not valid: {}", INSTALLATIONS_KEY, err);
+                vec![]
+            }
+        },
+        Ok(None) => vec![],
+        Err(err) => {
+            worker::console_log!("err: {:?}", err);
+            vec![]
+        }
+    }
+}
+
+/// The users who connected their workspace, as tenants.
+pub async fn installed_tenants(memory: &impl Memory) -> Vec<Tenant> {
+    let mut tenants = vec![];
+
+    for user in installed_users(memory).await {
+        let key = format!("{}{}", INSTALLATION_KEY_PREFIX, user);
+        match memory.get(&key).await {
+            Ok(Some(installation)) => match serde_json::from_str::<Installation>(&installation) {
+                Ok(installation) => tenants.push(installation.tenant()),
+                Err(err) => worker::console_log!("{} is not valid: {}", key, err),
+            },
+            Ok(None) => worker::console_log!("{} is missing", key),
+            Err(err) => worker::console_log!("err: {:?}", err),
+        };
+    }
+
+    tenants
+}
+
+fn query_param(req: &Request, name: &str) -> Result<Option<String>> {
+    let url = req.url()?;
+    let value = url
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string());
+    Ok(value)
+}
+
+/// `GET /oauth/authorize?user=<id>`, sends the browser to the Notion consent
+/// screen. `database_id` can name the database items go to.
+pub async fn authorize(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let options = match OAuthOptions::from_env(&ctx.env) {
+        Some(options) => options,
+        None => return Response::error("not found", 404),
+    };
+    let user = match query_param(&req, "user")? {
+        Some(user) => user,
+        None => return Response::error("user query parameter is required", 400),
+    };
+    if tenant::tenants_from_env(&ctx.env)
+        .iter()
+        .any(|tenant| tenant.id == user)
+    {
+        return Response::error(format!("tenant {} is configured in TENANTS", user), 409);
+    }
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+
+    match start_authorization(
+        &kv,
+        &options,
+        &user,
+        query_param(&req, "database_id")?,
+        Date::now().as_millis(),
+    )
+    .await
+    {
+        Ok(authorize_url) => Response::redirect(Url::parse(&authorize_url)?),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// `GET /oauth/callback`, the user coming back from the consent screen.
+pub async fn callback(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let options = match OAuthOptions::from_env(&ctx.env) {
+        Some(options) => options,
+        None => return Response::error("not found", 404),
+    };
+    if let Some(error) = query_param(&req, "error")? {
+        return OAuthError::Denied(error).into_response();
+    }
+    let (code, state) = match (query_param(&req, "code")?, query_param(&req, "state")?) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Response::error("code and state query parameters are required", 400),
+    };
+
+    let kv = ctx.kv("SUBSCRIPTIONS")?;
+
+    match complete_authorization(
+        &FetchClient,
+        &kv,
+        &options,
+        &code,
+        &state,
+        Date::now().as_millis(),
+    )
+    .await
+    {
+        Ok(installation) => Response::ok(format!(
+            "Notion is connected for {}, items are synced to database {}",
+            installation.user, installation.database_id
+        )),
+        Err(err) => {
+            worker::console_log!("failed to complete an authorization: {}", err);
+            err.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_auth::mock::SecretStore;
+    use crate::feed_auth::{PrefixedSecrets, Secrets};
+    use crate::http::mock::MockClient;
+    use crate::http::HttpResponse;
+    use crate::memory::mock::MemoryStore;
+    use serde_json::Value;
+    use wasm_bindgen_test::*;
+
+    const TOKEN_URL: &str = "https://oauth.example.com/token";
+    const NOW: u64 = 1667606400000;
+
+    fn options() -> OAuthOptions {
+        OAuthOptions {
+            client_id: "client id".to_string(),
+            client_secret: "client secret".to_string(),
+            base_url: "https://worker.example.com/".to_string(),
+            authorize_url: "https://oauth.example.com/authorize".to_string(),
+            token_url: TOKEN_URL.to_string(),
+        }
+    }
+
+    async fn state(memory: &MemoryStore, user: &str, database_id: Option<&str>) -> String {
+        let authorize_url = start_authorization(
+            memory,
+            &options(),
+            user,
+            database_id.map(|database_id| database_id.to_string()),
+            NOW,
+        )
+        .await
+        .unwrap();
+
+        Url::parse(&authorize_url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn redirect_to_the_consent_screen() {
+        let memory = MemoryStore::new();
+
+        let authorize_url = start_authorization(&memory, &options(), "alice", None, NOW)
+            .await
+            .unwrap();
+
+        let authorize_url = Url::parse(&authorize_url).unwrap();
+        assert_eq!(authorize_url.host_str(), Some("oauth.example.com"));
+        let params: HashMap<String, String> = authorize_url.query_pairs().into_owned().collect();
+        assert_eq!(params["client_id"], "client id");
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["owner"], "user");
+        assert_eq!(
+            params["redirect_uri"],
+            "https://worker.example.com/oauth/callback"
+        );
+        assert_eq!(params["state"].len(), STATE_LEN);
+        assert!(memory
+            .value(&format!("{}{}", STATE_KEY_PREFIX, params["state"]))
+            .is_some());
+
+        assert!(matches!(
+            start_authorization(&memory, &options(), "alice:bob", None, NOW).await,
+            Err(OAuthError::Invalid(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn store_the_token_with_the_database() {
+        let client = MockClient::new();
+        client.on(
+            Method::Post,
+            TOKEN_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "access_token": "alice token", "bot_id": "bot id", "workspace_name": "Alice", "owner": { "type": "user", "user": { "id": "alice user id" } } }"#,
+            ),
+        );
+        let memory = MemoryStore::new();
+        let state = state(&memory, "alice", Some("alice database id")).await;
+
+        let installation =
+            complete_authorization(&client, &memory, &options(), "code", &state, NOW + 1)
+                .await
+                .unwrap();
+        assert_eq!(installation.access_token, "alice token");
+        assert_eq!(installation.database_id, "alice database id");
+        assert_eq!(installation.notify_user_id, "alice user id");
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("Authorization"),
+            Some("Basic Y2xpZW50IGlkOmNsaWVudCBzZWNyZXQ=")
+        );
+        let body: Value = serde_json::from_str(requests[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "grant_type": "authorization_code",
+                "code": "code",
+                "redirect_uri": "https://worker.example.com/oauth/callback"
+            })
+        );
+
+        let tenants = installed_tenants(&memory).await;
+        assert_eq!(tenants.len(), 1);
+        assert_eq!(tenants[0].id, "alice");
+        let notion_command = tenants[0]
+            .notion_command(&crate::feed_auth::mock::SecretStore::new())
+            .unwrap();
+        assert_eq!(notion_command.notion_api_key, "alice token");
+        assert_eq!(notion_command.database_id, "alice database id");
+
+        // A state cannot be used twice
+        assert!(matches!(
+            complete_authorization(&client, &memory, &options(), "code", &state, NOW + 1).await,
+            Err(OAuthError::State)
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn keep_worker_secrets_from_installed_users() {
+        let client = MockClient::new();
+        client.on(
+            Method::Post,
+            TOKEN_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "access_token": "notion token", "owner": { "type": "user", "user": { "id": "user id" } } }"#,
+            ),
+        );
+        let memory = MemoryStore::new();
+        let state = state(&memory, "notion", Some("database id")).await;
+        complete_authorization(&client, &memory, &options(), "code", &state, NOW + 1)
+            .await
+            .unwrap();
+
+        let secrets = SecretStore::new()
+            .with("notion_api_key", "worker token")
+            .with("notion_oauth_client_secret", "client secret")
+            .with("tenant_notion_feed_token", "feed token");
+        let tenants = installed_tenants(&memory).await;
+        let secrets = PrefixedSecrets::new(&secrets, tenants[0].secret_prefix());
+        assert_eq!(secrets.secret("notion_api_key"), None);
+        assert_eq!(secrets.secret("notion_oauth_client_secret"), None);
+        assert_eq!(
+            secrets.secret("tenant_notion_feed_token"),
+            Some("feed token".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn pick_the_shared_database() {
+        let client = MockClient::new();
+        client.on(
+            Method::Post,
+            TOKEN_URL,
+            HttpResponse::new(200, r#"{ "access_token": "alice token" }"#),
+        );
+        client.on(
+            Method::Post,
+            SEARCH_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "results": [{ "object": "database", "id": "shared database id" }] }"#,
+            ),
+        );
+        client.on(
+            Method::Post,
+            SEARCH_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "results": [{ "object": "database", "id": "a" }, { "object": "database", "id": "b" }] }"#,
+            ),
+        );
+        let memory = MemoryStore::new();
+
+        let state_1 = state(&memory, "alice", None).await;
+        let installation =
+            complete_authorization(&client, &memory, &options(), "code", &state_1, NOW + 1)
+                .await
+                .unwrap();
+        assert_eq!(installation.database_id, "shared database id");
+        assert_eq!(
+            client.requests()[1].header("Authorization"),
+            Some("Bearer alice token")
+        );
+
+        let state_2 = state(&memory, "bob", None).await;
+        assert!(matches!(
+            complete_authorization(&client, &memory, &options(), "code", &state_2, NOW + 1).await,
+            Err(OAuthError::NoDatabase(_))
+        ));
+        assert_eq!(installed_tenants(&memory).await.len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_expired_states_and_failed_exchanges() {
+        let client = MockClient::new();
+        client.on(
+            Method::Post,
+            TOKEN_URL,
+            HttpResponse::new(400, r#"{ "error": "invalid_grant" }"#),
+        );
+        let memory = MemoryStore::new();
+
+        let state_1 = state(&memory, "alice", Some("alice database id")).await;
+        assert!(matches!(
+            complete_authorization(
+                &client,
+                &memory,
+                &options(),
+                "code",
+                &state_1,
+                NOW + STATE_TTL_MILLIS
+            )
+            .await,
+            Err(OAuthError::State)
+        ));
+        assert!(client.requests().is_empty());
+
+        let state_2 = state(&memory, "alice", Some("alice database id")).await;
+        assert!(matches!(
+            complete_authorization(&client, &memory, &options(), "code", &state_2, NOW + 1).await,
+            Err(OAuthError::Exchange(_))
+        ));
+        assert!(installed_tenants(&memory).await.is_empty());
+    }
+}
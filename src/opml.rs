@@ -0,0 +1,190 @@
+use crate::subscribe::SubscribedRSS;
+use roxmltree::{Document, Error, Node};
+use std::result::Result;
+
+#[derive(Debug, PartialEq)]
+pub struct OpmlFeed {
+    pub title: Option<String>,
+    pub rss_url: String,
+    pub categories: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum OpmlError {
+    Xml(Error),
+    Markup(String),
+}
+
+/// Reads every outline with an `xmlUrl` attribute. The texts of the outlines
+/// enclosing a feed and the paths in its `category` attribute become its
+/// categories.
+pub fn parse_opml(xml: &str) -> Result<Vec<OpmlFeed>, OpmlError> {
+    let document = match Document::parse(xml) {
+        Ok(document) => document,
+        Err(err) => return Err(OpmlError::Xml(err)),
+    };
+
+    let root = document.root_element();
+    if !root.has_tag_name("opml") {
+        return Err(OpmlError::Markup("root element should be opml".into()));
+    }
+
+    let body = match root.children().find(|child| child.has_tag_name("body")) {
+        Some(body) => body,
+        None => {
+            return Err(OpmlError::Markup(
+                "opml root element should have body element in its children".into(),
+            ))
+        }
+    };
+
+    let mut feeds = vec![];
+    collect_feeds(body, &mut vec![], &mut feeds);
+
+    Ok(feeds)
+}
+
+fn collect_feeds(parent: Node, categories: &mut Vec<String>, feeds: &mut Vec<OpmlFeed>) {
+    for outline in parent
+        .children()
+        .filter(|child| child.has_tag_name("outline"))
+    {
+        let title = outline
+            .attribute("title")
+            .or_else(|| outline.attribute("text"))
+            .map(|title| title.to_string());
+
+        match outline.attribute("xmlUrl") {
+            Some(rss_url) => {
+                let mut feed_categories = categories.clone();
+                // A category is a path like `/Tools/CLI`, kept whole so that
+                // tags like `ci/cd` come back as they were exported
+                if let Some(category) = outline.attribute("category") {
+                    for category in category
+                        .split(',')
+                        .map(|path| category_name(path.trim().trim_matches('/')))
+                    {
+                        if !category.is_empty() && !feed_categories.contains(&category) {
+                            feed_categories.push(category);
+                        }
+                    }
+                }
+
+                feeds.push(OpmlFeed {
+                    title,
+                    rss_url: rss_url.trim().to_string(),
+                    categories: feed_categories,
+                });
+            }
+            None => {
+                let category = title.as_deref().map(category_name).unwrap_or_default();
+                let is_category = !category.is_empty();
+                if is_category {
+                    categories.push(category);
+                }
+                collect_feeds(outline, categories, feeds);
+                if is_category {
+                    categories.pop();
+                }
+            }
+        }
+    }
+}
+
+// Notion rejects commas in multi-select options
+fn category_name(category: &str) -> String {
+    category.replace(',', "").trim().to_string()
+}
+
+pub fn to_opml(list: &[SubscribedRSS]) -> String {
+    let outlines: Vec<String> = list
+        .iter()
+        .map(|subscription| {
+            let rss_url = escape(&subscription.rss_url);
+            if subscription.tags.is_empty() {
+                format!(
+                    "    <outline type=\"rss\" text=\"{}\" xmlUrl=\"{}\"/>",
+                    rss_url, rss_url
+                )
+            } else {
+                format!(
+                    "    <outline type=\"rss\" text=\"{}\" xmlUrl=\"{}\" category=\"{}\"/>",
+                    rss_url,
+                    rss_url,
+                    escape(&subscription.tags.join(","))
+                )
+            }
+        })
+        .collect();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n  <head>\n    <title>rss-stream-to-notion subscriptions</title>\n  </head>\n  <body>\n{}\n  </body>\n</opml>\n",
+        outlines.join("\n")
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_enclosing_outlines_to_categories() {
+        let feeds = parse_opml("<opml version=\"2.0\"><head><title>feeds</title></head><body><outline text=\"Tech\"><outline text=\"Web\"><outline type=\"rss\" text=\"web.dev\" xmlUrl=\"https://web.dev/feed.xml\"/></outline><outline type=\"rss\" text=\"blog\" xmlUrl=\"https://example.com/feed.xml\" category=\"Rust,/Tools/CLI\"/></outline></body></opml>").unwrap();
+        assert_eq!(
+            feeds,
+            vec![
+                OpmlFeed {
+                    title: Some("web.dev".to_string()),
+                    rss_url: "https://web.dev/feed.xml".to_string(),
+                    categories: vec!["Tech".to_string(), "Web".to_string()],
+                },
+                OpmlFeed {
+                    title: Some("blog".to_string()),
+                    rss_url: "https://example.com/feed.xml".to_string(),
+                    categories: vec![
+                        "Tech".to_string(),
+                        "Rust".to_string(),
+                        "Tools/CLI".to_string(),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_non_opml_document() {
+        assert!(matches!(
+            parse_opml("<rss><channel></channel></rss>"),
+            Err(OpmlError::Markup(_))
+        ));
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let list = vec![
+            SubscribedRSS::new("https://web.dev/feed.xml", vec!["web", "ci/cd"]),
+            SubscribedRSS::new("https://example.com/feed.xml?a=1&b=2", vec![]),
+        ];
+        let feeds = parse_opml(&to_opml(&list)).unwrap();
+        assert_eq!(
+            feeds
+                .iter()
+                .map(|feed| (feed.rss_url.as_str(), feed.categories.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "https://web.dev/feed.xml",
+                    vec!["web".to_string(), "ci/cd".to_string()]
+                ),
+                ("https://example.com/feed.xml?a=1&b=2", vec![]),
+            ]
+        );
+    }
+}
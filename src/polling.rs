@@ -0,0 +1,181 @@
+use crate::feed_state_memory::FeedState;
+
+const MINUTE_MILLIS: u64 = 60 * 1000;
+const HOUR_MILLIS: u64 = 60 * MINUTE_MILLIS;
+const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+/// Feeds may ask to be polled rarely, but they are never left alone for
+/// longer than this because of their own hints.
+const MAX_HINTED_INTERVAL_MILLIS: u64 = DAY_MILLIS;
+
+/// The earliest time from `now` on when the feed may be fetched again, in
+/// milliseconds. The feed is due when this is `now`.
+///
+/// The wait after the last fetch is the longest of the subscription's
+/// `min_interval_minutes`, the feed's `ttl` and `sy:updatePeriod` and the
+/// `Cache-Control: max-age` of the last response. The hours and days the
+/// feed asked to be skipped are skipped on top of that.
+pub fn next_fetch_at(feed_state: &FeedState, min_interval_minutes: Option<u64>, now: u64) -> u64 {
+    let last_fetched_at = match feed_state.last_fetched_at {
+        Some(last_fetched_at) => last_fetched_at,
+        None => return now,
+    };
+
+    let hinted_interval_millis = [
+        feed_state
+            .ttl_minutes
+            .map(|ttl| ttl.saturating_mul(MINUTE_MILLIS)),
+        feed_state
+            .update_interval_minutes
+            .map(|update_interval| update_interval.saturating_mul(MINUTE_MILLIS)),
+        feed_state
+            .max_age_seconds
+            .map(|max_age| max_age.saturating_mul(1000)),
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .max()
+    .unwrap_or(0)
+    .min(MAX_HINTED_INTERVAL_MILLIS);
+    let min_interval_millis = min_interval_minutes
+        .unwrap_or(0)
+        .saturating_mul(MINUTE_MILLIS);
+    let interval_millis = hinted_interval_millis.max(min_interval_millis);
+
+    let next_fetch_at = last_fetched_at.saturating_add(interval_millis).max(now);
+
+    skip_hours_and_days(next_fetch_at, &feed_state.skip_hours, &feed_state.skip_days)
+}
+
+/// Moves `time` forward out of the skipped hours and days, all in UTC. A
+/// feed skipping every hour of the week is fetched at `time` anyway.
+fn skip_hours_and_days(time: u64, skip_hours: &[u32], skip_days: &[u32]) -> u64 {
+    let mut next = time;
+
+    for _ in 0..(8 * 24) {
+        if skip_days.contains(&weekday(next)) {
+            next = (next / DAY_MILLIS + 1) * DAY_MILLIS;
+        } else if skip_hours.contains(&hour(next)) {
+            next = (next / HOUR_MILLIS + 1) * HOUR_MILLIS;
+        } else {
+            return next;
+        }
+    }
+
+    time
+}
+
+fn hour(time: u64) -> u32 {
+    ((time / HOUR_MILLIS) % 24) as u32
+}
+
+/// 0 is Sunday, like in `skipDays`. The epoch was a Thursday.
+fn weekday(time: u64) -> u32 {
+    ((time / DAY_MILLIS + 4) % 7) as u32
+}
+
+/// Reads `max-age` out of a `Cache-Control` header, in seconds. Responses
+/// which should not be cached do not delay the next fetch.
+pub fn max_age(cache_control: Option<&str>) -> Option<u64> {
+    let cache_control = cache_control?;
+    let directives: Vec<&str> = cache_control
+        .split(',')
+        .map(|directive| directive.trim())
+        .collect();
+
+    if directives.iter().any(|directive| {
+        directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+    }) {
+        return None;
+    }
+
+    directives.iter().find_map(|directive| {
+        let (name, value) = directive.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("max-age") {
+            value.trim().trim_matches('"').parse::<u64>().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Thursday 1970-01-01 00:00 UTC
+    const THURSDAY: u64 = 0;
+
+    #[test]
+    fn fetch_never_fetched_feed_now() {
+        assert_eq!(next_fetch_at(&FeedState::default(), Some(60), 42), 42);
+    }
+
+    #[test]
+    fn wait_for_longest_interval() {
+        let feed_state = FeedState {
+            last_fetched_at: Some(THURSDAY),
+            ttl_minutes: Some(30),
+            update_interval_minutes: Some(60),
+            max_age_seconds: Some(600),
+            ..FeedState::default()
+        };
+
+        assert_eq!(next_fetch_at(&feed_state, None, 0), HOUR_MILLIS);
+        assert_eq!(next_fetch_at(&feed_state, Some(120), 0), 2 * HOUR_MILLIS);
+        assert_eq!(
+            next_fetch_at(&feed_state, None, 3 * HOUR_MILLIS),
+            3 * HOUR_MILLIS
+        );
+    }
+
+    #[test]
+    fn cap_hinted_interval() {
+        let feed_state = FeedState {
+            last_fetched_at: Some(THURSDAY),
+            ttl_minutes: Some(7 * 24 * 60),
+            ..FeedState::default()
+        };
+
+        assert_eq!(next_fetch_at(&feed_state, None, 0), DAY_MILLIS);
+        assert_eq!(
+            next_fetch_at(&feed_state, Some(2 * 24 * 60), 0),
+            2 * DAY_MILLIS
+        );
+    }
+
+    #[test]
+    fn skip_hours_and_days_of_feed() {
+        let feed_state = FeedState {
+            last_fetched_at: Some(THURSDAY),
+            ttl_minutes: Some(60),
+            skip_hours: vec![1, 2],
+            // Friday
+            skip_days: vec![5],
+            ..FeedState::default()
+        };
+
+        assert_eq!(next_fetch_at(&feed_state, None, 0), 3 * HOUR_MILLIS);
+        assert_eq!(next_fetch_at(&feed_state, None, DAY_MILLIS), 2 * DAY_MILLIS);
+    }
+
+    #[test]
+    fn ignore_skipping_every_hour() {
+        let feed_state = FeedState {
+            last_fetched_at: Some(THURSDAY),
+            skip_hours: (0..24).collect(),
+            ..FeedState::default()
+        };
+
+        assert_eq!(next_fetch_at(&feed_state, None, 0), 0);
+    }
+
+    #[test]
+    fn read_max_age() {
+        assert_eq!(max_age(None), None);
+        assert_eq!(max_age(Some("public, max-age=3600")), Some(3600));
+        assert_eq!(max_age(Some("Max-Age=\"60\"")), Some(60));
+        assert_eq!(max_age(Some("no-cache, max-age=3600")), None);
+        assert_eq!(max_age(Some("private")), None);
+    }
+}
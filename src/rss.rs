@@ -1,8 +1,9 @@
+use crate::html;
 use crate::store::StoreSchema;
 use insta;
-use roxmltree::{Document, Error};
+use roxmltree::{Document, Error, Node};
 use std::result::Result;
-use worker::{Date, DateInit};
+use worker::{Date, DateInit, Url};
 
 #[derive(Debug)]
 pub struct RssItem {
@@ -13,6 +14,10 @@ pub struct RssItem {
     pub article_url: Option<String>,
     pub categories: Vec<String>,
     pub description: String,
+    pub media: Media,
+    /// The picture used as the cover of the page.
+    pub thumbnail: Option<String>,
+    pub author: Option<String>,
 }
 
 impl RssItem {
@@ -33,8 +38,26 @@ impl RssItem {
             article_url,
             categories,
             description: description.into(),
+            media: Media::default(),
+            thumbnail: None,
+            author: None,
         }
     }
+
+    pub fn with_media(mut self, media: Media) -> Self {
+        self.media = media;
+        self
+    }
+
+    pub fn with_thumbnail(mut self, thumbnail: Option<String>) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    pub fn with_author(mut self, author: Option<String>) -> Self {
+        self.author = author;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -43,9 +66,420 @@ pub enum RssError {
     Markup(String),
 }
 
+const SYNDICATION_NAMESPACE: &str = "http://purl.org/rss/1.0/modules/syndication/";
+
+/// How often the publisher asks the feed to be polled.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PollingHints {
+    /// `<ttl>` of the channel.
+    pub ttl_minutes: Option<u64>,
+    /// `<skipHours>` of the channel, in UTC.
+    pub skip_hours: Vec<u32>,
+    /// `<skipDays>` of the channel, 0 being Sunday.
+    pub skip_days: Vec<u32>,
+    /// `sy:updatePeriod` divided by `sy:updateFrequency`.
+    pub update_interval_minutes: Option<u64>,
+}
+
+impl PollingHints {
+    fn from_channel(channel: Node) -> Self {
+        let ttl_minutes = channel
+            .children()
+            .find(|child| child.has_tag_name("ttl"))
+            .and_then(|ttl| ttl.text())
+            .and_then(|ttl| ttl.trim().parse::<u64>().ok());
+
+        let skip_hours = match channel
+            .children()
+            .find(|child| child.has_tag_name("skipHours"))
+        {
+            Some(skip_hours) => skip_hours
+                .children()
+                .filter(|child| child.has_tag_name("hour"))
+                .filter_map(|hour| hour.text())
+                .filter_map(|hour| hour.trim().parse::<u32>().ok())
+                // Some feeds use 24 for midnight
+                .map(|hour| hour % 24)
+                .collect(),
+            None => vec![],
+        };
+
+        let skip_days = match channel
+            .children()
+            .find(|child| child.has_tag_name("skipDays"))
+        {
+            Some(skip_days) => skip_days
+                .children()
+                .filter(|child| child.has_tag_name("day"))
+                .filter_map(|day| day.text())
+                .filter_map(|day| match day.trim() {
+                    "Sunday" => Some(0),
+                    "Monday" => Some(1),
+                    "Tuesday" => Some(2),
+                    "Wednesday" => Some(3),
+                    "Thursday" => Some(4),
+                    "Friday" => Some(5),
+                    "Saturday" => Some(6),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        let update_period_minutes = channel
+            .children()
+            .find(|child| child.has_tag_name((SYNDICATION_NAMESPACE, "updatePeriod")))
+            .and_then(|update_period| update_period.text())
+            .and_then(|update_period| match update_period.trim() {
+                "hourly" => Some(60),
+                "daily" => Some(24 * 60),
+                "weekly" => Some(7 * 24 * 60),
+                "monthly" => Some(30 * 24 * 60),
+                "yearly" => Some(365 * 24 * 60),
+                _ => None,
+            });
+        let update_frequency = channel
+            .children()
+            .find(|child| child.has_tag_name((SYNDICATION_NAMESPACE, "updateFrequency")))
+            .and_then(|update_frequency| update_frequency.text())
+            .and_then(|update_frequency| update_frequency.trim().parse::<u64>().ok())
+            .filter(|update_frequency| *update_frequency > 0)
+            .unwrap_or(1);
+        let update_interval_minutes = update_period_minutes
+            .map(|update_period_minutes| update_period_minutes / update_frequency);
+
+        Self {
+            ttl_minutes,
+            skip_hours,
+            skip_days,
+            update_interval_minutes,
+        }
+    }
+}
+
+/// `href` of the `<link>` or `<atom:link>` child of `channel` with the given
+/// `rel`.
+fn find_link(channel: Node, rel: &str) -> Option<String> {
+    channel
+        .children()
+        .filter(|child| child.has_tag_name("link"))
+        .find(|link| link.attribute("rel") == Some(rel))
+        .and_then(|link| link.attribute("href"))
+        .map(|href| href.trim().to_string())
+}
+
+/// The name of a `<category>`, which is its text in RSS and its `label` or
+/// `term` attribute in Atom.
+fn category_name<'a>(category: Node<'a, '_>) -> Option<&'a str> {
+    match category.text() {
+        Some(text) if !text.trim().is_empty() => Some(text),
+        _ => category
+            .attribute("label")
+            .or_else(|| category.attribute("term")),
+    }
+}
+
+const DUBLIN_CORE_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// The `dc:creator`, RSS `<author>` or Atom `<author><name>` of an item or a
+/// channel.
+fn find_author(node: Node) -> Option<String> {
+    node.children()
+        .filter(|child| {
+            child.has_tag_name((DUBLIN_CORE_NAMESPACE, "creator")) || child.has_tag_name("author")
+        })
+        .find_map(|author| {
+            let name = match author.children().find(|child| child.has_tag_name("name")) {
+                Some(name) => name.text(),
+                None => author.text(),
+            };
+            name.map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+        })
+}
+
+const MEDIA_NAMESPACE: &str = "http://search.yahoo.com/mrss/";
+const ITUNES_NAMESPACE: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+
+/// A file attached to an item, e.g. the audio of a podcast episode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// In bytes.
+    pub length: Option<u64>,
+}
+
+impl Enclosure {
+    fn from_element(element: Node, url_attribute: &str) -> Option<Self> {
+        let url = element
+            .attribute(url_attribute)
+            .map(|url| url.trim())
+            .filter(|url| !url.is_empty())?;
+
+        Some(Self {
+            url: url.to_string(),
+            mime_type: element
+                .attribute("type")
+                .map(|mime_type| mime_type.trim().to_string())
+                .filter(|mime_type| !mime_type.is_empty()),
+            length: element
+                .attribute("length")
+                .or_else(|| element.attribute("fileSize"))
+                .and_then(|length| length.trim().parse::<u64>().ok())
+                .filter(|length| *length > 0),
+        })
+    }
+
+    fn is_image(&self) -> bool {
+        match &self.mime_type {
+            Some(mime_type) => mime_type.starts_with("image/"),
+            None => false,
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        match &self.mime_type {
+            Some(mime_type) => mime_type.starts_with("audio/"),
+            None => false,
+        }
+    }
+}
+
+/// What podcast feeds tell about an episode.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Media {
+    /// `<enclosure>`, Atom `<link rel="enclosure">` or `media:content`.
+    pub enclosure: Option<Enclosure>,
+    /// `itunes:duration`.
+    pub duration_seconds: Option<u64>,
+    /// `itunes:episode`.
+    pub episode: Option<u64>,
+    /// `itunes:image`.
+    pub image: Option<String>,
+}
+
+impl Media {
+    fn from_item(item: Node) -> Self {
+        let enclosure = item
+            .children()
+            .filter(|child| child.has_tag_name("enclosure"))
+            .filter_map(|enclosure| Enclosure::from_element(enclosure, "url"))
+            .find(|enclosure| !enclosure.is_image())
+            .or_else(|| {
+                item.children()
+                    .filter(|child| child.has_tag_name("link"))
+                    .filter(|link| link.attribute("rel") == Some("enclosure"))
+                    .filter_map(|link| Enclosure::from_element(link, "href"))
+                    .find(|enclosure| !enclosure.is_image())
+            })
+            .or_else(|| {
+                item.children()
+                    .filter(|child| child.has_tag_name((MEDIA_NAMESPACE, "content")))
+                    .filter(|content| content.attribute("medium") != Some("image"))
+                    .filter_map(|content| Enclosure::from_element(content, "url"))
+                    .find(|enclosure| !enclosure.is_image())
+            });
+
+        let duration_seconds = item
+            .children()
+            .find(|child| child.has_tag_name((ITUNES_NAMESPACE, "duration")))
+            .and_then(|duration| duration.text())
+            .and_then(parse_duration);
+
+        let episode = item
+            .children()
+            .find(|child| child.has_tag_name((ITUNES_NAMESPACE, "episode")))
+            .and_then(|episode| episode.text())
+            .and_then(|episode| episode.trim().parse::<u64>().ok());
+
+        let image = item
+            .children()
+            .find(|child| child.has_tag_name((ITUNES_NAMESPACE, "image")))
+            .and_then(|image| image.attribute("href"))
+            .map(|href| href.trim().to_string())
+            .filter(|href| !href.is_empty());
+
+        Self {
+            enclosure,
+            duration_seconds,
+            episode,
+            image,
+        }
+    }
+}
+
+/// Parses `itunes:duration`, which is either seconds or `[HH:]MM:SS`.
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    let parts = duration.trim().split(':').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds: u64 = 0;
+    for (index, part) in parts.iter().enumerate() {
+        let part = part.trim();
+        // Only the seconds may have a fraction, which is dropped
+        let value = if index == parts.len() - 1 {
+            match part.parse::<f64>() {
+                Ok(value) if value >= 0.0 && value.is_finite() => value as u64,
+                _ => return None,
+            }
+        } else {
+            match part.parse::<u64>() {
+                Ok(value) => value,
+                Err(_) => return None,
+            }
+        };
+        // The text comes from the feed, so a huge value must not overflow
+        seconds = match seconds
+            .checked_mul(60)
+            .and_then(|seconds| seconds.checked_add(value))
+        {
+            Some(seconds) => seconds,
+            None => return None,
+        };
+    }
+
+    Some(seconds)
+}
+
+const CONTENT_NAMESPACE: &str = "http://purl.org/rss/1.0/modules/content/";
+
+/// `url` made absolute against `base_url`, if it ends up as an HTTP(S) URL.
+/// Notion rejects the whole page when a cover or an icon is not one.
+fn absolute_url(url: &str, base_url: Option<&str>) -> Option<String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => match base_url.and_then(|base_url| Url::parse(base_url).ok()) {
+            Some(base_url) => match base_url.join(url) {
+                Ok(url) => url,
+                Err(_) => return None,
+            },
+            None => return None,
+        },
+    };
+
+    match url.scheme() {
+        "http" | "https" => Some(url.to_string()),
+        _ => None,
+    }
+}
+
+/// The URL of the website a channel or an Atom feed belongs to.
+fn find_site_url(channel: Node) -> Option<String> {
+    channel
+        .children()
+        .filter(|child| child.has_tag_name("link"))
+        .find_map(|link| match link.text() {
+            Some(url) if !url.trim().is_empty() => Some(url.trim().to_string()),
+            _ => match link.attribute("rel") {
+                None | Some("alternate") => link.attribute("href").map(|href| href.to_string()),
+                Some(_) => None,
+            },
+        })
+}
+
+/// The channel `<image>`, `itunes:image` or Atom `<icon>` of a feed.
+fn find_icon(channel: Node) -> Option<String> {
+    let site_url = find_site_url(channel);
+
+    channel
+        .children()
+        .filter(|child| child.has_tag_name("image") || child.has_tag_name("icon"))
+        .find_map(|image| {
+            let url = match image.children().find(|child| child.has_tag_name("url")) {
+                Some(url) => url.text(),
+                None => image.attribute("href").or_else(|| image.text()),
+            };
+            url.and_then(|url| absolute_url(url, site_url.as_deref()))
+        })
+}
+
+/// The picture of an item: its `media:thumbnail`, image `media:content` or
+/// image enclosure, the `itunes:image` of the episode, or else the first
+/// `<img>` of its content.
+fn find_thumbnail(item: Node, article_url: Option<&str>) -> Option<String> {
+    // `media:thumbnail` may also be nested in `media:group` or `media:content`
+    let media_image = item.descendants().find_map(|element| {
+        let is_image = element.has_tag_name((MEDIA_NAMESPACE, "thumbnail"))
+            || (element.has_tag_name((MEDIA_NAMESPACE, "content"))
+                && (element.attribute("medium") == Some("image")
+                    || element
+                        .attribute("type")
+                        .map(|mime_type| mime_type.starts_with("image/"))
+                        .unwrap_or(false)));
+        if !is_image {
+            return None;
+        }
+        element
+            .attribute("url")
+            .and_then(|url| absolute_url(url, article_url))
+    });
+    if media_image.is_some() {
+        return media_image;
+    }
+
+    let enclosure_image = item
+        .children()
+        .filter(|child| {
+            child.has_tag_name("enclosure")
+                || (child.has_tag_name("link") && child.attribute("rel") == Some("enclosure"))
+        })
+        .filter(|enclosure| {
+            enclosure
+                .attribute("type")
+                .map(|mime_type| mime_type.starts_with("image/"))
+                .unwrap_or(false)
+        })
+        .find_map(|enclosure| {
+            enclosure
+                .attribute("url")
+                .or_else(|| enclosure.attribute("href"))
+                .and_then(|url| absolute_url(url, article_url))
+        });
+    if enclosure_image.is_some() {
+        return enclosure_image;
+    }
+
+    let episode_image = item
+        .children()
+        .find(|child| child.has_tag_name((ITUNES_NAMESPACE, "image")))
+        .and_then(|image| image.attribute("href"))
+        .and_then(|href| absolute_url(href, article_url));
+    if episode_image.is_some() {
+        return episode_image;
+    }
+
+    item.children()
+        .filter(|child| {
+            child.has_tag_name((CONTENT_NAMESPACE, "encoded"))
+                || child.has_tag_name("description")
+                || child.has_tag_name("content")
+                || child.has_tag_name("summary")
+        })
+        .filter_map(|content| content.text())
+        .flat_map(|content| html::find_tags(content, "img"))
+        .find_map(|img| {
+            img.attribute("src")
+                .and_then(|src| absolute_url(src, article_url))
+        })
+}
+
 #[derive(Debug)]
 pub struct Rss {
     pub items: Vec<RssItem>,
+    pub polling: PollingHints,
+    /// The WebSub hub the feed advertises.
+    pub hub_url: Option<String>,
+    /// The canonical URL of the feed, used as the WebSub topic.
+    pub self_url: Option<String>,
+    /// The picture used as the icon of the pages.
+    pub icon_url: Option<String>,
 }
 
 impl Rss {
@@ -98,7 +532,7 @@ impl Rss {
 
         let root_categories = root_categories_elements
             .map(
-                |root_categories_element| match root_categories_element.text() {
+                |root_categories_element| match category_name(root_categories_element) {
                     Some(root_category) => root_category.to_string(),
                     None => "".to_string(),
                 },
@@ -190,18 +624,21 @@ impl Rss {
                     .filter(|child| child.has_tag_name("category"));
 
                 let article_categories = article_categories_elements
-                    .map(
-                        |article_categories_element| match article_categories_element.text() {
+                    .map(|article_categories_element| {
+                        match category_name(article_categories_element) {
                             Some(article_category) => article_category.to_string(),
                             None => "".to_string(),
-                        },
-                    )
+                        }
+                    })
                     .filter(|article_category| article_category.len() > 0);
 
                 let mut categories = article_categories.collect::<Vec<String>>();
 
                 categories.extend_from_slice(&root_categories.clone().collect::<Vec<String>>());
 
+                let thumbnail = find_thumbnail(item, article_url.as_deref());
+                let author = find_author(item).or_else(|| find_author(channel));
+
                 Ok(RssItem::new(
                     id,
                     blog_title,
@@ -210,13 +647,22 @@ impl Rss {
                     article_url,
                     categories,
                     description,
-                ))
+                )
+                .with_media(Media::from_item(item))
+                .with_thumbnail(thumbnail)
+                .with_author(author))
             })
             .filter(|item| item.is_ok())
             .map(|item| item.unwrap())
             .collect();
 
-        Ok(Self { items })
+        Ok(Self {
+            items,
+            polling: PollingHints::from_channel(channel),
+            hub_url: find_link(channel, "hub"),
+            self_url: find_link(channel, "self"),
+            icon_url: find_icon(channel),
+        })
     }
 
     fn from_feed_element_root(document: Document) -> Result<Self, RssError> {
@@ -246,7 +692,7 @@ impl Rss {
 
         let root_categories = root_categories_elements
             .map(
-                |root_categories_element| match root_categories_element.text() {
+                |root_categories_element| match category_name(root_categories_element) {
                     Some(root_category) => root_category.to_string(),
                     None => "".to_string(),
                 },
@@ -332,18 +778,22 @@ impl Rss {
                     .filter(|child| child.has_tag_name("category"));
 
                 let article_categories = article_categories_elements
-                    .map(
-                        |article_categories_element| match article_categories_element.text() {
+                    .map(|article_categories_element| {
+                        match category_name(article_categories_element) {
                             Some(article_category) => article_category.to_string(),
                             None => "".to_string(),
-                        },
-                    )
+                        }
+                    })
                     .filter(|article_category| article_category.len() > 0);
 
                 let mut categories = article_categories.collect::<Vec<String>>();
 
                 categories.extend_from_slice(&root_categories.clone().collect::<Vec<String>>());
 
+                let thumbnail = find_thumbnail(item, article_url.as_deref());
+                // Entries without an author inherit the one of the feed
+                let author = find_author(item).or_else(|| find_author(feed));
+
                 Ok(RssItem::new(
                     id,
                     blog_title,
@@ -352,13 +802,22 @@ impl Rss {
                     article_url,
                     categories,
                     description,
-                ))
+                )
+                .with_media(Media::from_item(item))
+                .with_thumbnail(thumbnail)
+                .with_author(author))
             })
             .filter(|item| item.is_ok())
             .map(|item| item.unwrap())
             .collect();
 
-        Ok(Self { items })
+        Ok(Self {
+            items,
+            polling: PollingHints::from_channel(feed),
+            hub_url: find_link(feed, "hub"),
+            self_url: find_link(feed, "self"),
+            icon_url: find_icon(feed),
+        })
     }
 
     pub fn exclude_latest_published_date(&self) -> Date {
@@ -431,4 +890,118 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn read_polling_hints() {
+        let rss = Rss::from_xml("<rss xmlns:sy=\"http://purl.org/rss/1.0/modules/syndication/\" version=\"2.0\"><channel><title>blog title</title><ttl>60</ttl><skipHours><hour>0</hour><hour>24</hour><hour>3</hour></skipHours><skipDays><day>Saturday</day><day>Sunday</day></skipDays><sy:updatePeriod>daily</sy:updatePeriod><sy:updateFrequency>4</sy:updateFrequency></channel></rss>");
+        assert_eq!(
+            rss.unwrap().polling,
+            PollingHints {
+                ttl_minutes: Some(60),
+                skip_hours: vec![0, 0, 3],
+                skip_days: vec![6, 0],
+                update_interval_minutes: Some(6 * 60),
+            }
+        )
+    }
+
+    #[test]
+    fn read_websub_links() {
+        let rss = Rss::from_xml("<rss xmlns:atom=\"http://www.w3.org/2005/Atom\" version=\"2.0\"><channel><title>blog title</title><link>https://example.com</link><atom:link rel=\"hub\" href=\"https://hub.example.com/\"/><atom:link rel=\"self\" href=\"https://example.com/feed.xml\"/></channel></rss>").unwrap();
+        assert_eq!(rss.hub_url, Some("https://hub.example.com/".to_string()));
+        assert_eq!(
+            rss.self_url,
+            Some("https://example.com/feed.xml".to_string())
+        );
+
+        let rss = Rss::from_xml("<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>blog title</title><link rel=\"hub\" href=\"https://hub.example.com/\"/><link rel=\"alternate\" href=\"https://example.com\"/></feed>").unwrap();
+        assert_eq!(rss.hub_url, Some("https://hub.example.com/".to_string()));
+        assert_eq!(rss.self_url, None);
+    }
+
+    #[test]
+    fn read_podcast_metadata() {
+        let rss = Rss::from_xml("<rss xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" version=\"2.0\"><channel><title>podcast title</title><item><title>episode 1</title><enclosure url=\"https://example.com/cover.jpg\" type=\"image/jpeg\"/><enclosure url=\"https://example.com/1.mp3\" type=\"audio/mpeg\" length=\"1234\"/><itunes:duration>1:02:03</itunes:duration><itunes:episode>1</itunes:episode><itunes:image href=\"https://example.com/1.jpg\"/></item><item><title>episode 2</title></item></channel></rss>").unwrap();
+        assert_eq!(
+            rss.items[0].media,
+            Media {
+                enclosure: Some(Enclosure {
+                    url: "https://example.com/1.mp3".to_string(),
+                    mime_type: Some("audio/mpeg".to_string()),
+                    length: Some(1234),
+                }),
+                duration_seconds: Some(3723),
+                episode: Some(1),
+                image: Some("https://example.com/1.jpg".to_string()),
+            }
+        );
+        assert_eq!(rss.items[1].media, Media::default());
+
+        let rss = Rss::from_xml("<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:media=\"http://search.yahoo.com/mrss/\"><title>podcast title</title><entry><title>episode 1</title><link rel=\"enclosure\" href=\"https://example.com/1.m4a\" type=\"audio/mp4\"/></entry><entry><title>episode 2</title><media:content url=\"https://example.com/2.jpg\" medium=\"image\"/><media:content url=\"https://example.com/2.ogg\" fileSize=\"42\"/></entry></feed>").unwrap();
+        assert_eq!(
+            rss.items[0]
+                .media
+                .enclosure
+                .as_ref()
+                .map(|enclosure| enclosure.url.as_str()),
+            Some("https://example.com/1.m4a")
+        );
+        assert_eq!(
+            rss.items[1].media.enclosure,
+            Some(Enclosure {
+                url: "https://example.com/2.ogg".to_string(),
+                mime_type: None,
+                length: Some(42),
+            })
+        );
+    }
+
+    #[test]
+    fn read_thumbnails_and_icon() {
+        let rss = Rss::from_xml("<rss xmlns:media=\"http://search.yahoo.com/mrss/\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\" version=\"2.0\"><channel><title>blog title</title><link>https://example.com/blog/</link><image><url>/icon.png</url><title>blog title</title><link>https://example.com/blog/</link></image><item><title>article title 1</title><media:group><media:thumbnail url=\"https://example.com/1.jpg\"/></media:group></item><item><title>article title 2</title><enclosure url=\"https://example.com/2.png\" type=\"image/png\"/></item><item><title>article title 3</title><link>https://example.com/blog/3</link><description>no picture</description><content:encoded><![CDATA[<p>text</p><img src=\"images/3.png\"><img src=\"/4.png\">]]></content:encoded></item><item><title>article title 4</title><description>&lt;img src=\"data:image/png;base64,AAAA\"&gt;</description></item></channel></rss>").unwrap();
+        assert_eq!(
+            rss.icon_url,
+            Some("https://example.com/icon.png".to_string())
+        );
+        assert_eq!(
+            rss.items
+                .iter()
+                .map(|item| item.thumbnail.as_deref())
+                .collect::<Vec<Option<&str>>>(),
+            vec![
+                Some("https://example.com/1.jpg"),
+                Some("https://example.com/2.png"),
+                Some("https://example.com/blog/images/3.png"),
+                None,
+            ]
+        );
+
+        let rss = Rss::from_xml("<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>blog title</title><link rel=\"self\" href=\"https://example.com/feed.xml\"/><icon>https://example.com/favicon.ico</icon><entry><title>article title 1</title><content type=\"html\">&lt;img src=\"https://example.com/1.png\"&gt;</content></entry></feed>").unwrap();
+        assert_eq!(
+            rss.icon_url,
+            Some("https://example.com/favicon.ico".to_string())
+        );
+        assert_eq!(
+            rss.items[0].thumbnail,
+            Some("https://example.com/1.png".to_string())
+        );
+    }
+
+    #[test]
+    fn read_atom_category_attributes() {
+        let rss = Rss::from_xml("<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>blog title</title><category term=\"blog\"/><entry><title>article title 1</title><category term=\"rust\" label=\"Rust\"/><category term=\"wasm\"/><category/></entry></feed>").unwrap();
+        assert_eq!(rss.items[0].categories, vec!["Rust", "wasm", "blog"]);
+    }
+
+    #[test]
+    fn parse_itunes_duration() {
+        assert_eq!(parse_duration("3723"), Some(3723));
+        assert_eq!(parse_duration("62:03"), Some(3723));
+        assert_eq!(parse_duration("01:02:03"), Some(3723));
+        assert_eq!(parse_duration(" 12.5 "), Some(12));
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("an hour"), None);
+        assert_eq!(parse_duration("18446744073709551615:00"), None);
+        assert_eq!(parse_duration("1:18446744073709551615"), None);
+    }
 }
@@ -0,0 +1,552 @@
+use crate::rss::RssItem;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+/// The part of an item a rule is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Description,
+    Categories,
+    Author,
+}
+
+const ALL_FIELDS: [Field; 4] = [
+    Field::Title,
+    Field::Description,
+    Field::Categories,
+    Field::Author,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    /// Found anywhere in the title, description or author, ignoring case.
+    /// Categories have to be equal to it, ignoring case.
+    Keyword(String),
+    /// A regular expression, case-sensitive unless it starts with `(?i)`.
+    Regex(String),
+}
+
+/// A keyword or a regex matched against some fields of the items of a feed,
+/// e.g. `{ "keyword": "sponsored", "fields": ["title"] }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Identifies the rule in the sync report, it is named after its
+    /// pattern otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub pattern: Pattern,
+    /// Every field is matched when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<Field>,
+}
+
+impl Rule {
+    pub fn name(&self) -> String {
+        match (&self.name, &self.pattern) {
+            (Some(name), _) => name.clone(),
+            (None, Pattern::Keyword(keyword)) => format!("keyword:{}", keyword),
+            (None, Pattern::Regex(regex)) => format!("regex:{}", regex),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.pattern {
+            Pattern::Keyword(keyword) if keyword.trim().is_empty() => {
+                Err("keyword of a rule should not be empty".into())
+            }
+            Pattern::Keyword(_) => Ok(()),
+            Pattern::Regex(regex) => match Regex::new(regex) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(format!("regex {} of a rule is not valid: {}", regex, err)),
+            },
+        }
+    }
+
+    fn compile(&self) -> CompiledRule {
+        let matcher = match &self.pattern {
+            Pattern::Keyword(keyword) => Matcher::Keyword(keyword.trim().to_lowercase()),
+            Pattern::Regex(regex) => match Regex::new(regex) {
+                Ok(regex) => Matcher::Regex(regex),
+                Err(err) => {
+                    // Subscriptions are validated when they are saved, but the
+                    // fallback list of `wrangler.toml` is not
+                    worker::console_log!("rule {} is not valid: {}", self.name(), err);
+                    Matcher::Never
+                }
+            },
+        };
+        let fields = if self.fields.is_empty() {
+            ALL_FIELDS.to_vec()
+        } else {
+            self.fields.clone()
+        };
+
+        CompiledRule {
+            name: self.name(),
+            matcher,
+            fields,
+        }
+    }
+}
+
+enum Matcher {
+    Keyword(String),
+    Regex(Regex),
+    /// An invalid regex matches nothing.
+    Never,
+}
+
+struct CompiledRule {
+    name: String,
+    matcher: Matcher,
+    fields: Vec<Field>,
+}
+
+impl CompiledRule {
+    fn matches(&self, item: &RssItem) -> bool {
+        self.fields.iter().any(|field| match field {
+            Field::Title => self.matches_text(&item.article_title),
+            Field::Description => self.matches_text(&item.description),
+            Field::Author => match &item.author {
+                Some(author) => self.matches_text(author),
+                None => false,
+            },
+            Field::Categories => item.categories.iter().any(|category| match &self.matcher {
+                Matcher::Keyword(keyword) => category.trim().to_lowercase() == *keyword,
+                _ => self.matches_text(category),
+            }),
+        })
+    }
+
+    fn matches_text(&self, text: &str) -> bool {
+        match &self.matcher {
+            Matcher::Keyword(keyword) => text.to_lowercase().contains(keyword.as_str()),
+            Matcher::Regex(regex) => regex.is_match(text),
+            Matcher::Never => false,
+        }
+    }
+}
+
+/// Why an item is left out of Notion, named in the sync report.
+pub const NOT_INCLUDED: &str = "include";
+
+/// The include and exclude rules of a subscription, compiled once per sync.
+pub struct Filter {
+    include: Vec<CompiledRule>,
+    exclude: Vec<CompiledRule>,
+}
+
+impl Filter {
+    pub fn new(include: &[Rule], exclude: &[Rule]) -> Self {
+        Self {
+            include: include.iter().map(Rule::compile).collect(),
+            exclude: exclude.iter().map(Rule::compile).collect(),
+        }
+    }
+
+    /// The name of the rule which drops `item`, if any. When there are include
+    /// rules, items none of them match are dropped as `NOT_INCLUDED`.
+    pub fn drops(&self, item: &RssItem) -> Option<String> {
+        if !self.include.is_empty() && !self.include.iter().any(|rule| rule.matches(item)) {
+            return Some(NOT_INCLUDED.to_string());
+        }
+
+        self.exclude
+            .iter()
+            .find(|rule| rule.matches(item))
+            .map(|rule| rule.name.clone())
+    }
+}
+
+/// Adds `tags` to the items a rule matches, e.g.
+/// `{ "keyword": "react", "fields": ["title"], "tags": ["react"] }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagRule {
+    #[serde(flatten)]
+    pub rule: Rule,
+    pub tags: Vec<String>,
+}
+
+impl TagRule {
+    pub fn validate(&self) -> Result<(), String> {
+        self.rule.validate()?;
+
+        if self.tags.is_empty() {
+            return Err(format!("tagging rule {} should add tags", self.rule.name()));
+        }
+        for tag in &self.tags {
+            if tag.trim().is_empty() {
+                return Err("tags should not be empty".into());
+            }
+            // Notion rejects commas in multi-select options
+            if tag.contains(',') {
+                return Err(format!("tag {} should not contain comma", tag));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The rules applied to every feed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GlobalRules {
+    pub tag_rules: Vec<TagRule>,
+    pub routes: Vec<RouteRule>,
+}
+
+impl GlobalRules {
+    /// Reads the tagging rules from the `TAG_RULES` variable and the routes
+    /// from the `NOTION_ROUTES` variable, both as JSON. Invalid rules are
+    /// logged and left out.
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            tag_rules: read_rules(env, "TAG_RULES", TagRule::validate),
+            routes: read_rules(env, "NOTION_ROUTES", RouteRule::validate),
+        }
+    }
+}
+
+fn read_rules<T: for<'de> Deserialize<'de>>(
+    env: &Env,
+    name: &str,
+    validate: fn(&T) -> Result<(), String>,
+) -> Vec<T> {
+    let rules = match env.var(name) {
+        Ok(rules) if !rules.to_string().trim().is_empty() => rules.to_string(),
+        _ => return vec![],
+    };
+
+    let rules: Vec<T> = match serde_json::from_str(&rules) {
+        Ok(rules) => rules,
+        Err(err) => {
+            worker::console_log!("{} is not valid and is ignored: {}", name, err);
+            return vec![];
+        }
+    };
+    rules
+        .into_iter()
+        .filter(|rule| match validate(rule) {
+            Ok(_) => true,
+            Err(err) => {
+                worker::console_log!("{} has an invalid rule: {}", name, err);
+                false
+            }
+        })
+        .collect()
+}
+
+/// The global and per-feed tagging rules of a subscription, compiled once
+/// per sync.
+pub struct Tagger {
+    rules: Vec<(CompiledRule, Vec<String>)>,
+}
+
+impl Tagger {
+    pub fn new(global: &[TagRule], feed: &[TagRule]) -> Self {
+        Self {
+            rules: global
+                .iter()
+                .chain(feed)
+                .map(|tag_rule| (tag_rule.rule.compile(), tag_rule.tags.clone()))
+                .collect(),
+        }
+    }
+
+    /// `tags` followed by the tags of the rules matching `item`, without
+    /// duplicates.
+    pub fn tags(&self, item: &RssItem, tags: &[String]) -> Vec<String> {
+        let mut merged = tags.to_vec();
+
+        for (rule, rule_tags) in &self.rules {
+            if !rule.matches(item) {
+                continue;
+            }
+            for tag in rule_tags {
+                if !merged.contains(tag) {
+                    merged.push(tag.clone());
+                }
+            }
+        }
+
+        merged
+    }
+}
+
+/// Sends the items having one of `tags` and matching `rule` to the Notion
+/// target named `target`, e.g.
+/// `{ "target": "advisories", "tags": ["security"] }` or
+/// `{ "target": "releases", "match": { "regex": "^v?\\d+\\.\\d+", "fields": ["title"] } }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteRule {
+    pub target: String,
+    /// Any tag of the item, after the tagging rules, matches when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<Rule>,
+}
+
+impl RouteRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.target.trim().is_empty() {
+            return Err("target of a route should not be empty".into());
+        }
+        if let Some(rule) = &self.rule {
+            rule.validate()?;
+        }
+        if self.tags.is_empty() && self.rule.is_none() {
+            return Err(format!(
+                "route to {} should have tags or a match rule",
+                self.target
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The routes of every feed, compiled once per sync.
+pub struct Router {
+    routes: Vec<(String, Vec<String>, Option<CompiledRule>)>,
+}
+
+impl Router {
+    pub fn new(routes: &[RouteRule]) -> Self {
+        Self {
+            routes: routes
+                .iter()
+                .map(|route| {
+                    (
+                        route.target.clone(),
+                        route.tags.clone(),
+                        route.rule.as_ref().map(Rule::compile),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The target of the first route matching `item` tagged with `tags`.
+    pub fn target(&self, item: &RssItem, tags: &[String]) -> Option<String> {
+        self.routes
+            .iter()
+            .find(|(_, route_tags, rule)| {
+                let tagged =
+                    route_tags.is_empty() || route_tags.iter().any(|tag| tags.contains(tag));
+                let matched = match rule {
+                    Some(rule) => rule.matches(item),
+                    None => true,
+                };
+                tagged && matched
+            })
+            .map(|(target, _, _)| target.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(
+        title: &str,
+        description: &str,
+        categories: Vec<&str>,
+        author: Option<&str>,
+    ) -> RssItem {
+        RssItem::new(
+            "id",
+            "blog title",
+            title,
+            None,
+            None,
+            categories
+                .iter()
+                .map(|category| category.to_string())
+                .collect(),
+            description,
+        )
+        .with_author(author.map(|author| author.to_string()))
+    }
+
+    fn rules(json: &str) -> Vec<Rule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn read_rules() {
+        assert_eq!(
+            rules(
+                r#"[{ "keyword": "Rust", "fields": ["title", "categories"] }, { "name": "ads", "regex": "^\\[PR\\]" }]"#
+            ),
+            vec![
+                Rule {
+                    name: None,
+                    pattern: Pattern::Keyword("Rust".to_string()),
+                    fields: vec![Field::Title, Field::Categories],
+                },
+                Rule {
+                    name: Some("ads".to_string()),
+                    pattern: Pattern::Regex("^\\[PR\\]".to_string()),
+                    fields: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rules() {
+        assert!(rules(r#"[{ "regex": "^a+$" }]"#)[0].validate().is_ok());
+        assert!(rules(r#"[{ "regex": "(" }]"#)[0].validate().is_err());
+        assert!(rules(r#"[{ "keyword": " " }]"#)[0].validate().is_err());
+    }
+
+    #[test]
+    fn drop_excluded_items() {
+        let filter = Filter::new(
+            &[],
+            &rules(
+                r#"[{ "keyword": "sponsored", "fields": ["title", "description"] }, { "name": "ads", "regex": "^\\[PR\\]" }, { "keyword": "go", "fields": ["categories"] }]"#,
+            ),
+        );
+
+        assert_eq!(filter.drops(&item("Rust 1.65", "", vec![], None)), None);
+        assert_eq!(
+            filter.drops(&item("A post", "This post is SPONSORED", vec![], None)),
+            Some("keyword:sponsored".to_string())
+        );
+        assert_eq!(
+            filter.drops(&item("[PR] A product", "", vec![], None)),
+            Some("ads".to_string())
+        );
+        assert_eq!(
+            filter.drops(&item("A post", "", vec!["Go"], None)),
+            Some("keyword:go".to_string())
+        );
+        // Categories have to be equal to the keyword
+        assert_eq!(
+            filter.drops(&item("A post", "", vec!["Google"], None)),
+            None
+        );
+    }
+
+    #[test]
+    fn drop_items_not_included() {
+        let filter = Filter::new(
+            &rules(r#"[{ "keyword": "rust" }, { "regex": "(?i)^alice", "fields": ["author"] }]"#),
+            &rules(r#"[{ "keyword": "rumor" }]"#),
+        );
+
+        assert_eq!(filter.drops(&item("Rust 1.65", "", vec![], None)), None);
+        assert_eq!(filter.drops(&item("A post", "", vec!["rust"], None)), None);
+        assert_eq!(
+            filter.drops(&item("A post", "", vec![], Some("Alice"))),
+            None
+        );
+        assert_eq!(
+            filter.drops(&item("A post", "", vec![], Some("Bob"))),
+            Some(NOT_INCLUDED.to_string())
+        );
+        assert_eq!(
+            filter.drops(&item("Rust rumor", "", vec![], None)),
+            Some("keyword:rumor".to_string())
+        );
+    }
+
+    #[test]
+    fn tag_matching_items() {
+        let global: Vec<TagRule> = serde_json::from_str(
+            r#"[{ "keyword": "react", "fields": ["title", "categories"], "tags": ["react"] }, { "regex": "(?i)\\b(CVE|XSS)\\b", "tags": ["security"] }]"#,
+        )
+        .unwrap();
+        let feed: Vec<TagRule> =
+            serde_json::from_str(r#"[{ "keyword": "rust", "tags": ["rust", "web"] }]"#).unwrap();
+        let tagger = Tagger::new(&global, &feed);
+        let tags = vec!["web".to_string()];
+
+        assert_eq!(
+            tagger.tags(&item("React 18", "", vec![], None), &tags),
+            vec!["web", "react"]
+        );
+        assert_eq!(
+            tagger.tags(
+                &item("A post", "Fixing an xss in Rust", vec![], None),
+                &tags
+            ),
+            vec!["web", "security", "rust"]
+        );
+        assert_eq!(
+            tagger.tags(&item("A post", "", vec!["React"], None), &[]),
+            vec!["react"]
+        );
+        assert_eq!(
+            tagger.tags(&item("A post", "", vec![], None), &tags),
+            vec!["web"]
+        );
+    }
+
+    #[test]
+    fn validate_tag_rules() {
+        let tag_rules: Vec<TagRule> = serde_json::from_str(
+            r#"[{ "keyword": "rust", "tags": ["rust"] }, { "keyword": "rust", "tags": [] }, { "keyword": "rust", "tags": ["a,b"] }]"#,
+        )
+        .unwrap();
+        assert!(tag_rules[0].validate().is_ok());
+        assert!(tag_rules[1].validate().is_err());
+        assert!(tag_rules[2].validate().is_err());
+    }
+
+    #[test]
+    fn invalid_regex_matches_nothing() {
+        let filter = Filter::new(&[], &rules(r#"[{ "regex": "(" }]"#));
+        assert_eq!(filter.drops(&item("(", "", vec![], None)), None);
+    }
+
+    #[test]
+    fn route_items_to_the_first_matching_target() {
+        let routes: Vec<RouteRule> = serde_json::from_str(
+            r#"[{ "target": "advisories", "tags": ["security"] }, { "target": "releases", "tags": ["rust"], "match": { "regex": "^Rust \\d+", "fields": ["title"] } }, { "target": "reading list", "match": { "keyword": "team" } }]"#,
+        )
+        .unwrap();
+        let router = Router::new(&routes);
+        let tags =
+            |tags: &[&str]| -> Vec<String> { tags.iter().map(|tag| tag.to_string()).collect() };
+
+        assert_eq!(
+            router.target(
+                &item("Rust 1.65", "", vec![], None),
+                &tags(&["rust", "security"])
+            ),
+            Some("advisories".to_string())
+        );
+        assert_eq!(
+            router.target(&item("Rust 1.65", "", vec![], None), &tags(&["rust"])),
+            Some("releases".to_string())
+        );
+        assert_eq!(
+            router.target(&item("Rust 1.65", "", vec![], None), &tags(&["web"])),
+            None
+        );
+        assert_eq!(
+            router.target(&item("A post", "For the team", vec![], None), &[]),
+            Some("reading list".to_string())
+        );
+    }
+
+    #[test]
+    fn validate_routes() {
+        let routes: Vec<RouteRule> = serde_json::from_str(
+            r#"[{ "target": "releases", "tags": ["rust"] }, { "target": "releases" }, { "target": " ", "tags": ["rust"] }, { "target": "releases", "match": { "regex": "(" } }]"#,
+        )
+        .unwrap();
+        assert!(routes[0].validate().is_ok());
+        assert!(routes[1].validate().is_err());
+        assert!(routes[2].validate().is_err());
+        assert!(routes[3].validate().is_err());
+    }
+}
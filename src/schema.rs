@@ -0,0 +1,592 @@
+use crate::http::{FetchClient, HttpClient};
+use crate::notion::{NotionCommand, NotionCommandError, PropertyMapping};
+use crate::sync;
+use crate::tenant;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use worker::{Env, Method, Request, Response, Result, RouteContext};
+
+const DATABASES_URL: &str = "https://api.notion.com/v1/databases";
+
+/// The properties pages are created with, the type Notion expects for them
+/// and whether only podcast episodes have them.
+pub const PROPERTIES: [(&str, &str, bool); 11] = [
+    ("article_title", "title", false),
+    ("blog_name", "rich_text", false),
+    ("tags", "multi_select", false),
+    ("guid", "rich_text", false),
+    ("description", "rich_text", false),
+    ("link", "url", false),
+    ("published_date", "date", false),
+    ("categories", "multi_select", false),
+    ("audio_url", "url", true),
+    ("duration", "number", true),
+    ("episode", "number", true),
+];
+
+/// Whether the databases are checked before every sync, read from the
+/// `SCHEMA_CHECK` variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaCheck {
+    Off,
+    /// Logs and reports the differences.
+    Report,
+    /// Also adds the missing properties to the databases.
+    Fix,
+}
+
+impl FromStr for SchemaCheck {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim() {
+            "" | "off" => Ok(SchemaCheck::Off),
+            "report" => Ok(SchemaCheck::Report),
+            "fix" => Ok(SchemaCheck::Fix),
+            value => Err(format!("{} is not a schema check", value)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PropertyDiff {
+    pub name: String,
+    pub expected: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+/// How a database differs from the pages created in it.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SchemaReport {
+    /// The target the database is used for, the default database when
+    /// `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub database_id: String,
+    /// Pages are rejected by Notion until these are added.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<PropertyDiff>,
+    /// Only podcast episodes are rejected until these are added.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_optional: Vec<PropertyDiff>,
+    /// Never changed, as changing the type of a property can lose data.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mistyped: Vec<PropertyDiff>,
+    /// The missing properties which were added to the database.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SchemaReport {
+    /// Whether the report is worth telling about after a sync.
+    pub fn has_changes(&self) -> bool {
+        !self.missing.is_empty()
+            || !self.mistyped.is_empty()
+            || !self.added.is_empty()
+            || self.error.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+struct DatabaseResponse {
+    properties: HashMap<String, DatabaseProperty>,
+}
+
+#[derive(Deserialize)]
+struct DatabaseProperty {
+    #[serde(rename = "type")]
+    property_type: String,
+}
+
+#[derive(Deserialize)]
+struct CreatedDatabase {
+    id: String,
+}
+
+/// The names and types the properties have in a database with `mapping`,
+/// and whether only podcast episodes have them.
+fn expected_properties(
+    mapping: &PropertyMapping,
+    categories: bool,
+    podcasts: bool,
+) -> Vec<(String, String, bool)> {
+    PROPERTIES
+        .iter()
+        .filter(|(property, _, _)| categories || *property != "categories")
+        .filter(|(_, _, podcast)| podcasts || !podcast)
+        .filter_map(|(property, property_type, podcast)| {
+            mapping
+                .name(property)
+                .map(|name| (name, property_type.to_string(), *podcast))
+        })
+        .collect()
+}
+
+/// Compares every database of `notion_command` with the properties of the
+/// pages created in it. With `fix`, missing properties are added through
+/// the database update endpoint.
+pub async fn check(
+    client: &impl HttpClient,
+    notion_command: &NotionCommand,
+    fix: bool,
+) -> Vec<SchemaReport> {
+    let mut reports = vec![];
+
+    for (target, database_id, mapping) in notion_command.databases() {
+        reports
+            .push(check_database(client, notion_command, target, database_id, &mapping, fix).await);
+    }
+
+    reports
+}
+
+async fn check_database(
+    client: &impl HttpClient,
+    notion_command: &NotionCommand,
+    target: Option<String>,
+    database_id: String,
+    mapping: &PropertyMapping,
+    fix: bool,
+) -> SchemaReport {
+    let url = format!("{}/{}", DATABASES_URL, database_id);
+    let mut report = SchemaReport {
+        target,
+        database_id,
+        ..SchemaReport::default()
+    };
+
+    let response = match notion_command
+        .request(client, Method::Get, &url, None)
+        .await
+    {
+        Ok(response) => response,
+        Err(err) => {
+            report.error = Some(format!("failed to retrieve the database: {:?}", err));
+            return report;
+        }
+    };
+    let database: DatabaseResponse = match serde_json::from_str(&response.body) {
+        Ok(database) => database,
+        Err(err) => {
+            report.error = Some(format!("failed to read the database: {}", err));
+            return report;
+        }
+    };
+
+    for (name, property_type, podcast) in expected_properties(
+        mapping,
+        notion_command.syncs_categories(),
+        notion_command.syncs_podcasts(),
+    ) {
+        match database.properties.get(&name) {
+            Some(property) if property.property_type == property_type => {}
+            Some(property) => report.mistyped.push(PropertyDiff {
+                name,
+                expected: property_type,
+                actual: Some(property.property_type.clone()),
+            }),
+            None if podcast => report.missing_optional.push(PropertyDiff {
+                name,
+                expected: property_type,
+                actual: None,
+            }),
+            None => report.missing.push(PropertyDiff {
+                name,
+                expected: property_type,
+                actual: None,
+            }),
+        }
+    }
+
+    if !fix || report.missing.is_empty() {
+        return report;
+    }
+
+    let mut properties = Map::new();
+    for diff in &report.missing {
+        if diff.expected == "title" {
+            // A database has exactly one title property, which is renamed
+            if let Some((current, _)) = database
+                .properties
+                .iter()
+                .find(|(_, property)| property.property_type == "title")
+            {
+                properties.insert(current.clone(), json!({ "name": diff.name }));
+            }
+        } else {
+            properties.insert(diff.name.clone(), property_definition(&diff.expected));
+        }
+    }
+
+    match notion_command
+        .request(
+            client,
+            Method::Patch,
+            &url,
+            Some(json!({ "properties": properties }).to_string()),
+        )
+        .await
+    {
+        Ok(_) => {
+            report.added = report.missing.drain(..).map(|diff| diff.name).collect();
+        }
+        Err(err) => {
+            report.error = Some(format!("failed to add the missing properties: {:?}", err));
+        }
+    };
+
+    report
+}
+
+fn property_definition(property_type: &str) -> Value {
+    let mut definition = Map::new();
+    definition.insert(property_type.to_string(), json!({}));
+    Value::Object(definition)
+}
+
+/// Creates a database with every property pages can be created with, named
+/// after the mapping of the default database, under the page
+/// `parent_page_id`. Returns the ID of the new database.
+pub async fn create_database(
+    client: &impl HttpClient,
+    notion_command: &NotionCommand,
+    parent_page_id: &str,
+    title: &str,
+) -> std::result::Result<String, NotionCommandError> {
+    let mapping = match notion_command.databases().into_iter().next() {
+        Some((_, _, mapping)) => mapping,
+        None => PropertyMapping::default(),
+    };
+
+    let mut properties = Map::new();
+    for (name, property_type, _) in expected_properties(&mapping, true, true) {
+        properties.insert(name, property_definition(&property_type));
+    }
+    let body = json!({
+        "parent": { "type": "page_id", "page_id": parent_page_id },
+        "title": [{ "type": "text", "text": { "content": title } }],
+        "properties": properties,
+    });
+
+    let response = match notion_command
+        .request(client, Method::Post, DATABASES_URL, Some(body.to_string()))
+        .await
+    {
+        Ok(response) => response,
+        Err(err) => return Err(err),
+    };
+    match serde_json::from_str::<CreatedDatabase>(&response.body) {
+        Ok(database) => Ok(database.id),
+        Err(err) => Err(NotionCommandError::SerializeError(err)),
+    }
+}
+
+fn query_param(req: &Request, name: &str) -> Result<Option<String>> {
+    let url = req.url()?;
+    let value = url
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string());
+    Ok(value)
+}
+
+/// The command of the tenant named by the `tenant` query parameter, or of
+/// the default workspace. The error is the reason there is none.
+async fn scoped_notion_command(
+    req: &Request,
+    env: &Env,
+) -> Result<std::result::Result<NotionCommand, String>> {
+    let id = match query_param(req, "tenant")? {
+        Some(id) => id,
+        None => return Ok(Ok(sync::notion_command(env)?)),
+    };
+
+    let kv = env.kv("SUBSCRIPTIONS")?;
+    let tenant = match tenant::all_tenants(env, &kv)
+        .await
+        .into_iter()
+        .find(|tenant| tenant.id == id)
+    {
+        Some(tenant) => tenant,
+        None => return Ok(Err(format!("tenant {} is not configured", id))),
+    };
+
+    match tenant.notion_command(env) {
+        Some(notion_command) => Ok(Ok(sync::with_env_options(env, notion_command))),
+        None => Ok(Err(format!(
+            "{} secret is not set",
+            tenant.notion_api_key_secret
+        ))),
+    }
+}
+
+/// `GET /schema` reports how the databases differ from the pages created in
+/// them, `POST /schema` also adds the missing properties.
+pub async fn get_schema(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    schema(req, ctx, false).await
+}
+
+pub async fn post_schema(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    schema(req, ctx, true).await
+}
+
+async fn schema(req: Request, ctx: RouteContext<()>, fix: bool) -> Result<Response> {
+    let notion_command = match scoped_notion_command(&req, &ctx.env).await? {
+        Ok(notion_command) => notion_command,
+        Err(message) => return Response::error(message, 404),
+    };
+
+    Response::from_json(&check(&FetchClient, &notion_command, fix).await)
+}
+
+/// `POST /schema/database?parent_page_id=<id>&title=<title>`, creates a
+/// database pages can be created in.
+pub async fn post_database(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let parent_page_id = match query_param(&req, "parent_page_id")? {
+        Some(parent_page_id) => parent_page_id,
+        None => return Response::error("parent_page_id query parameter is required", 400),
+    };
+    let title = query_param(&req, "title")?.unwrap_or_else(|| "Feeds".to_string());
+    let notion_command = match scoped_notion_command(&req, &ctx.env).await? {
+        Ok(notion_command) => notion_command,
+        Err(message) => return Response::error(message, 404),
+    };
+
+    match create_database(&FetchClient, &notion_command, &parent_page_id, &title).await {
+        Ok(database_id) => {
+            Ok(Response::from_json(&json!({ "database_id": database_id }))?.with_status(201))
+        }
+        Err(err) => {
+            worker::console_log!("failed to create a database: {:?}", err);
+            Response::error(format!("failed to create a database: {:?}", err), 502)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::MockClient;
+    use crate::http::HttpResponse;
+    use wasm_bindgen_test::*;
+
+    const DATABASE_URL: &str = "https://api.notion.com/v1/databases/database id";
+
+    fn notion_command() -> NotionCommand {
+        NotionCommand::build(
+            "database id".to_string(),
+            "notify user id".to_string(),
+            "notion api key".to_string(),
+        )
+    }
+
+    #[test]
+    fn read_schema_check() {
+        assert_eq!("".parse(), Ok(SchemaCheck::Off));
+        assert_eq!("report".parse(), Ok(SchemaCheck::Report));
+        assert_eq!(" fix".parse(), Ok(SchemaCheck::Fix));
+        assert!("repair".parse::<SchemaCheck>().is_err());
+    }
+
+    #[test]
+    fn map_expected_properties() {
+        let mapping: PropertyMapping =
+            serde_json::from_str(r#"{ "article_title": "Name", "guid": null }"#).unwrap();
+
+        let properties = expected_properties(&mapping, false, true);
+        assert_eq!(
+            properties[0],
+            ("Name".to_string(), "title".to_string(), false)
+        );
+        assert!(properties.iter().all(|(name, _, _)| name != "guid"));
+        assert!(properties.iter().all(|(name, _, _)| name != "categories"));
+        assert_eq!(properties.len(), 9);
+        assert_eq!(expected_properties(&mapping, true, true).len(), 10);
+        assert_eq!(expected_properties(&mapping, false, false).len(), 6);
+    }
+
+    #[test]
+    fn reject_duplicated_property_names() {
+        assert!(serde_json::from_str::<PropertyMapping>(
+            r#"{ "blog_name": "Name", "article_title": "Name" }"#
+        )
+        .is_err());
+        // Unlisted properties keep their name, which is taken too
+        assert!(serde_json::from_str::<PropertyMapping>(r#"{ "blog_name": "guid" }"#).is_err());
+        assert!(serde_json::from_str::<PropertyMapping>(
+            r#"{ "blog_name": "guid", "guid": "Id" }"#
+        )
+        .is_ok());
+        assert!(
+            serde_json::from_str::<PropertyMapping>(r#"{ "blog_name": null, "guid": null }"#)
+                .is_ok()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn report_differences() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            DATABASE_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "object": "database", "properties": {
+                    "Name": { "id": "title", "type": "title" },
+                    "blog_name": { "id": "a", "type": "rich_text" },
+                    "tags": { "id": "b", "type": "select" },
+                    "guid": { "id": "c", "type": "rich_text" },
+                    "description": { "id": "d", "type": "rich_text" },
+                    "link": { "id": "e", "type": "url" }
+                } }"#,
+            ),
+        );
+
+        let reports = check(&client, &notion_command().with_podcasts(true), false).await;
+
+        assert_eq!(
+            reports,
+            vec![SchemaReport {
+                target: None,
+                database_id: "database id".to_string(),
+                missing: vec![
+                    PropertyDiff {
+                        name: "article_title".to_string(),
+                        expected: "title".to_string(),
+                        actual: None,
+                    },
+                    PropertyDiff {
+                        name: "published_date".to_string(),
+                        expected: "date".to_string(),
+                        actual: None,
+                    },
+                ],
+                missing_optional: vec![
+                    PropertyDiff {
+                        name: "audio_url".to_string(),
+                        expected: "url".to_string(),
+                        actual: None,
+                    },
+                    PropertyDiff {
+                        name: "duration".to_string(),
+                        expected: "number".to_string(),
+                        actual: None,
+                    },
+                    PropertyDiff {
+                        name: "episode".to_string(),
+                        expected: "number".to_string(),
+                        actual: None,
+                    },
+                ],
+                mistyped: vec![PropertyDiff {
+                    name: "tags".to_string(),
+                    expected: "multi_select".to_string(),
+                    actual: Some("select".to_string()),
+                }],
+                added: vec![],
+                error: None,
+            }]
+        );
+        assert!(reports[0].has_changes());
+    }
+
+    #[wasm_bindgen_test]
+    async fn add_missing_properties() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            DATABASE_URL,
+            HttpResponse::new(
+                200,
+                r#"{ "properties": { "Name": { "type": "title" }, "tags": { "type": "multi_select" } } }"#,
+            ),
+        );
+        client.on(Method::Patch, DATABASE_URL, HttpResponse::new(200, "{}"));
+
+        let reports = check(&client, &notion_command(), true).await;
+
+        assert_eq!(
+            reports[0].added,
+            vec![
+                "article_title",
+                "blog_name",
+                "guid",
+                "description",
+                "link",
+                "published_date"
+            ]
+        );
+        assert!(reports[0].missing.is_empty());
+        // Podcast properties are only expected when podcasts are synced
+        assert!(reports[0].missing_optional.is_empty());
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, Method::Patch);
+        let body: Value = serde_json::from_str(requests[1].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({ "properties": {
+                "Name": { "name": "article_title" },
+                "blog_name": { "rich_text": {} },
+                "guid": { "rich_text": {} },
+                "description": { "rich_text": {} },
+                "link": { "url": {} },
+                "published_date": { "date": {} }
+            } })
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn report_unreachable_databases() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            DATABASE_URL,
+            HttpResponse::new(404, r#"{ "object": "error", "code": "object_not_found" }"#),
+        );
+
+        let reports = check(&client, &notion_command(), true).await;
+
+        assert!(reports[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("object_not_found"));
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn create_a_database_under_a_page() {
+        let client = MockClient::new();
+        client.on(
+            Method::Post,
+            DATABASES_URL,
+            HttpResponse::new(200, r#"{ "object": "database", "id": "new database id" }"#),
+        );
+
+        let database_id = create_database(&client, &notion_command(), "page id", "Feeds")
+            .await
+            .unwrap();
+        assert_eq!(database_id, "new database id");
+
+        let requests = client.requests();
+        let body: Value = serde_json::from_str(requests[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            body["parent"],
+            json!({ "type": "page_id", "page_id": "page id" })
+        );
+        assert_eq!(body["properties"]["article_title"], json!({ "title": {} }));
+        assert_eq!(
+            body["properties"]["categories"],
+            json!({ "multi_select": {} })
+        );
+        assert_eq!(body["properties"]["duration"], json!({ "number": {} }));
+        assert_eq!(body["properties"].as_object().unwrap().len(), 11);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::rss::Media;
 use rand::Rng;
 use worker::{Date, DateInit};
 
@@ -11,11 +12,19 @@ pub struct StoreSchema {
     pub article_title: String,
     pub rss_url: String,
     pub tags: Vec<String>,
+    /// The categories of the item, as the feed spells them.
+    pub categories: Vec<String>,
     pub description: String,
     pub read: bool,
     pub guid: String,
     pub link: Option<String>,
     pub published_date: Option<Date>,
+    pub media: Media,
+    pub cover: Option<String>,
+    pub icon: Option<String>,
+    /// The Notion target the page is created in, the default database when
+    /// `None`.
+    pub target: Option<String>,
 }
 
 impl StoreSchema {
@@ -42,6 +51,7 @@ impl StoreSchema {
             article_title: article_title.into(),
             rss_url: rss_url.into(),
             tags,
+            categories: vec![],
             description: description.into(),
             read: false,
             guid,
@@ -52,6 +62,35 @@ impl StoreSchema {
                 }
                 None => None,
             },
+            media: Media::default(),
+            cover: None,
+            icon: None,
+            target: None,
         }
     }
+
+    pub fn with_media(mut self, media: Media) -> Self {
+        self.media = media;
+        self
+    }
+
+    pub fn with_categories(mut self, categories: Vec<String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    pub fn with_cover(mut self, cover: Option<String>) -> Self {
+        self.cover = cover;
+        self
+    }
+
+    pub fn with_icon(mut self, icon: Option<String>) -> Self {
+        self.icon = icon;
+        self
+    }
+
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
 }
@@ -1,55 +1,660 @@
+use crate::feed_auth::{Credentials, FeedAuth, Secrets};
+use crate::html;
+use crate::http::{HttpClient, HttpRequest, HttpResponse};
+use crate::memory::Memory;
+use crate::polling;
 use crate::rss::{Rss, RssError};
+use crate::rules::{Rule, TagRule};
+use crate::utils;
+use futures::future::{self, Either};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
 use std::result::Result;
-use worker::{Date, Error, Fetch, Method, Request};
+use worker::{Env, Error, Method, Url};
 
+const SUBSCRIPTIONS_KEY: &str = "subscriptions";
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SubscribedRSS {
     pub rss_url: String,
+    #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub paused: bool,
+    /// The feed is not fetched more often than this, whatever it says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_interval_minutes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<FeedAuth>,
+    /// Only items matching one of these rules are pushed when there are any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Rule>,
+    /// Items matching one of these rules are not pushed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<Rule>,
+    /// Tags added to the items of this feed only, on top of `TAG_RULES`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tag_rules: Vec<TagRule>,
+    /// The `NOTION_TARGETS` entry items of this feed go to, unless a route
+    /// of `NOTION_ROUTES` matches them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
 }
 
+#[derive(Debug)]
 pub enum SubscribedError {
     RssError(RssError),
     WorkerError(Error),
+    HttpStatus(u16),
+    /// The feed did not respond within the given milliseconds.
+    Timeout(u64),
+    /// The feed is larger than the given bytes.
+    TooLarge(usize),
+    TooManyRedirects,
+    /// The feed answered 410 Gone and will not come back.
+    Gone,
+    /// The Worker secret holding the credential of the feed is not set.
+    MissingSecret(String),
+    /// The URL is a web page which does not link to any feed.
+    NoFeedFound,
+}
+
+pub struct FetchOptions {
+    pub timeout_millis: u64,
+    pub max_body_bytes: usize,
+    pub user_agent: String,
+    pub accept: String,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            timeout_millis: 10_000,
+            max_body_bytes: 5 * 1024 * 1024,
+            user_agent: "rss-stream-to-notion (+https://github.com/Shinyaigeek/rss-stream-to-notion)"
+                .to_string(),
+            accept: "application/rss+xml, application/atom+xml, application/xml;q=0.9, text/xml;q=0.8, */*;q=0.5"
+                .to_string(),
+        }
+    }
+}
+
+impl FetchOptions {
+    pub fn from_env(env: &Env) -> Self {
+        let default = Self::default();
+        Self {
+            timeout_millis: utils::var_or(env, "FEED_TIMEOUT_MILLIS", default.timeout_millis),
+            max_body_bytes: utils::var_or(env, "FEED_MAX_BYTES", default.max_body_bytes),
+            user_agent: utils::var_or(env, "FEED_USER_AGENT", default.user_agent),
+            accept: utils::var_or(env, "FEED_ACCEPT", default.accept),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FetchedRss {
+    Modified {
+        rss: Rss,
+        validators: CacheValidators,
+        /// Where the feed permanently moved, or the feed discovered on the
+        /// web page at the subscribed URL.
+        moved_to: Option<String>,
+        /// `Cache-Control: max-age` of the response, in seconds.
+        max_age: Option<u64>,
+    },
+    NotModified {
+        max_age: Option<u64>,
+    },
 }
 
 impl SubscribedRSS {
-    fn new(rss_url: impl Into<String>, tags: Vec<&str>) -> Self {
+    pub fn new(rss_url: impl Into<String>, tags: Vec<&str>) -> Self {
         Self {
             rss_url: rss_url.into(),
             tags: tags.iter().map(|&tag| tag.into()).collect(),
+            paused: false,
+            min_interval_minutes: None,
+            auth: None,
+            include: vec![],
+            exclude: vec![],
+            tag_rules: vec![],
+            target: None,
         }
     }
 
-    pub async fn into_rss(self) -> Result<Rss, SubscribedError> {
-        let request = match Request::new(&self.rss_url, Method::Get) {
-            Ok(request) => request,
-            Err(err) => {
-                // TODO(#1) Inherite error information to log more detailed error
-                return Err(SubscribedError::WorkerError(err));
+    pub fn validate(&self) -> Result<(), String> {
+        match Url::parse(&self.rss_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => {
+                return Err(format!(
+                    "rss_url should be an http(s) URL, but got {} scheme",
+                    url.scheme()
+                ))
             }
+            Err(err) => return Err(format!("rss_url {} is not a URL: {}", self.rss_url, err)),
         };
-        let mut response = match Fetch::Request(request).send().await {
-            Ok(response) => response,
-            Err(err) => {
-                // TODO(#1) Inherite error information to log more detailed error
-                return Err(SubscribedError::WorkerError(err));
+
+        for tag in &self.tags {
+            if tag.trim().is_empty() {
+                return Err("tags should not be empty".into());
+            }
+            // Notion rejects commas in multi-select options
+            if tag.contains(',') {
+                return Err(format!("tag {} should not contain comma", tag));
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            auth.validate()?;
+        }
+
+        for rule in self.include.iter().chain(&self.exclude) {
+            rule.validate()?;
+        }
+        for tag_rule in &self.tag_rules {
+            tag_rule.validate()?;
+        }
+        if let Some(target) = &self.target {
+            if target.trim().is_empty() {
+                return Err("target should not be empty".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the feed, sending `validators` from the last response as
+    /// `If-None-Match` and `If-Modified-Since`. The body is only parsed when
+    /// the feed has changed since then. Redirects are followed here rather
+    /// than by the client, so that a feed which moved permanently can be
+    /// reported through `moved_to`. The credentials of a private feed are
+    /// only sent to the origin of `rss_url`, and are redacted from errors.
+    ///
+    /// When `rss_url` is a web page rather than a feed, the first feed it
+    /// links to is fetched instead and reported through `moved_to` as well.
+    pub async fn fetch_rss(
+        &self,
+        client: &impl HttpClient,
+        secrets: &impl Secrets,
+        validators: &CacheValidators,
+        options: &FetchOptions,
+    ) -> Result<FetchedRss, SubscribedError> {
+        let credentials = match &self.auth {
+            Some(auth) => match auth.credentials(secrets) {
+                Ok(credentials) => Some(credentials),
+                Err(secret_name) => return Err(SubscribedError::MissingSecret(secret_name)),
+            },
+            None => None,
+        };
+
+        let (response, url, mut moved_to) = match self
+            .fetch_document(client, &credentials, &self.rss_url, validators, options)
+            .await?
+        {
+            FetchedDocument::Modified {
+                response,
+                url,
+                moved_to,
+            } => (response, url, moved_to),
+            FetchedDocument::NotModified { max_age } => {
+                return Ok(FetchedRss::NotModified { max_age })
             }
         };
-        let rss_text = match response.text().await {
-            Ok(rss_text) => rss_text,
-            Err(err) => {
-                // TODO(#1) Inherite error information to log more detailed error
-                return Err(SubscribedError::WorkerError(err));
+
+        let response = if html::is_html(response.header("Content-Type"), &response.body) {
+            let feed_url = match html::discover_feeds(&response.body, &url).first() {
+                Some(feed_url) => feed_url.clone(),
+                None => return Err(SubscribedError::NoFeedFound),
+            };
+
+            // The validators belong to the page, not to the feed
+            match self
+                .fetch_document(
+                    client,
+                    &credentials,
+                    &feed_url,
+                    &CacheValidators::default(),
+                    options,
+                )
+                .await?
+            {
+                FetchedDocument::Modified {
+                    response,
+                    moved_to: feed_moved_to,
+                    ..
+                } => {
+                    moved_to = Some(feed_moved_to.unwrap_or(feed_url));
+                    response
+                }
+                FetchedDocument::NotModified { .. } => {
+                    return Err(SubscribedError::HttpStatus(304))
+                }
             }
+        } else {
+            response
+        };
+        if html::is_html(response.header("Content-Type"), &response.body) {
+            return Err(SubscribedError::NoFeedFound);
+        }
+
+        let max_age = polling::max_age(response.header("Cache-Control"));
+        let validators = CacheValidators {
+            etag: response.header("ETag").map(|etag| etag.to_string()),
+            last_modified: response
+                .header("Last-Modified")
+                .map(|last_modified| last_modified.to_string()),
         };
+        let rss_text = response.body;
 
         match Rss::from_xml(&rss_text) {
-            Ok(rss) => Ok(rss),
+            Ok(rss) => Ok(FetchedRss::Modified {
+                rss,
+                validators,
+                moved_to,
+                max_age,
+            }),
             Err(err) => Err(SubscribedError::RssError(err)),
         }
     }
+
+    async fn fetch_document(
+        &self,
+        client: &impl HttpClient,
+        credentials: &Option<Credentials>,
+        url: &str,
+        validators: &CacheValidators,
+        options: &FetchOptions,
+    ) -> Result<FetchedDocument, SubscribedError> {
+        let origin = Url::parse(&self.rss_url).map(|url| url.origin());
+
+        let mut url = url.to_string();
+        let mut moved_to = None;
+        let mut moved_permanently = true;
+        let mut redirects = 0;
+
+        let response = loop {
+            let mut request = HttpRequest::new(Method::Get, &url)
+                .with_header("User-Agent", &options.user_agent)
+                .with_header("Accept", &options.accept)
+                .with_max_body_bytes(options.max_body_bytes)
+                .with_follow_redirects(false);
+            if let Some(etag) = &validators.etag {
+                request = request.with_header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.with_header("If-Modified-Since", last_modified);
+            }
+            if let Some(credentials) = credentials {
+                if Url::parse(&url).map(|url| url.origin()) == origin {
+                    request = credentials.apply(request);
+                }
+            }
+
+            let response = match send_with_timeout(client, request, options.timeout_millis).await {
+                Ok(response) => response,
+                Err(SubscribedError::WorkerError(err)) => {
+                    return Err(SubscribedError::WorkerError(match credentials {
+                        Some(credentials) => Error::RustError(credentials.redact(&err.to_string())),
+                        None => err,
+                    }))
+                }
+                Err(err) => return Err(err),
+            };
+
+            if ![301, 302, 303, 307, 308].contains(&response.status) {
+                break response;
+            }
+
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(SubscribedError::TooManyRedirects);
+            }
+
+            let location = match response.header("Location") {
+                Some(location) => location,
+                None => return Err(SubscribedError::HttpStatus(response.status)),
+            };
+            url = match Url::parse(&url).and_then(|base| base.join(location)) {
+                // A feed may send its credential back in the location
+                Ok(location) => match credentials {
+                    Some(credentials) => credentials.redact(location.as_str()),
+                    None => location.to_string(),
+                },
+                Err(_) => return Err(SubscribedError::HttpStatus(response.status)),
+            };
+
+            // Only a chain made of permanent redirects means the feed moved
+            if moved_permanently && (response.status == 301 || response.status == 308) {
+                moved_to = Some(url.clone());
+            } else {
+                moved_permanently = false;
+            }
+        };
+
+        if response.status == 410 {
+            return Err(SubscribedError::Gone);
+        }
+        if response.status == 304 {
+            return Ok(FetchedDocument::NotModified {
+                max_age: polling::max_age(response.header("Cache-Control")),
+            });
+        }
+        if !(200..300).contains(&response.status) {
+            return Err(SubscribedError::HttpStatus(response.status));
+        }
+        let content_length = response
+            .header("Content-Length")
+            .and_then(|content_length| content_length.parse::<usize>().ok());
+        if content_length.unwrap_or(0) > options.max_body_bytes
+            || response.body.len() > options.max_body_bytes
+        {
+            return Err(SubscribedError::TooLarge(options.max_body_bytes));
+        }
+
+        Ok(FetchedDocument::Modified {
+            response,
+            url,
+            moved_to,
+        })
+    }
+}
+
+enum FetchedDocument {
+    Modified {
+        response: HttpResponse,
+        /// Where the response came from once redirects are followed.
+        url: String,
+        moved_to: Option<String>,
+    },
+    NotModified {
+        max_age: Option<u64>,
+    },
+}
+
+/// Gives up on `request` after `timeout_millis`. The request is dropped
+/// then, which aborts its fetch through the signal `FetchClient` gives it.
+async fn send_with_timeout(
+    client: &impl HttpClient,
+    request: HttpRequest,
+    timeout_millis: u64,
+) -> Result<HttpResponse, SubscribedError> {
+    let send = Box::pin(client.send(request));
+    let timeout = Box::pin(utils::sleep(timeout_millis));
+    match future::select(send, timeout).await {
+        Either::Left((Ok(response), _)) => Ok(response),
+        Either::Left((Err(err), _)) => {
+            // TODO(#1) Inherite error information to log more detailed error
+            Err(SubscribedError::WorkerError(err))
+        }
+        Either::Right(_) => Err(SubscribedError::Timeout(timeout_millis)),
+    }
+}
+
+#[derive(Debug)]
+pub enum SubscribeListError {
+    WorkerError(Error),
+    Json(serde_json::Error),
+    Invalid(String),
+    InvalidEntry { index: usize, message: String },
+}
+
+impl fmt::Display for SubscribeListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscribeListError::WorkerError(err) => {
+                write!(f, "failed to load subscriptions: {}", err)
+            }
+            SubscribeListError::Json(err) => write!(f, "subscriptions are not valid JSON: {}", err),
+            SubscribeListError::Invalid(message) => write!(f, "{}", message),
+            SubscribeListError::InvalidEntry { index, message } => {
+                write!(f, "subscription #{} is invalid: {}", index, message)
+            }
+        }
+    }
+}
+
+/// Loads the subscriptions stored in `memory`. When nothing has been stored
+/// yet, `fallback` (the `SUBSCRIPTION_LIST` environment variable) is used instead.
+pub async fn load_subscribe_list(
+    memory: &impl Memory,
+    fallback: Option<String>,
+) -> Result<Vec<SubscribedRSS>, SubscribeListError> {
+    let stored = match memory.get(SUBSCRIPTIONS_KEY).await {
+        Ok(stored) => stored,
+        Err(err) => return Err(SubscribeListError::WorkerError(err)),
+    };
+
+    match stored.or(fallback) {
+        Some(json) => parse_subscribe_list(&json),
+        None => Ok(vec![]),
+    }
+}
+
+pub fn subscription_list_fallback(env: &Env) -> Option<String> {
+    match env.var("SUBSCRIPTION_LIST") {
+        Ok(var) => Some(var.to_string()),
+        Err(_) => None,
+    }
+}
+
+pub async fn save_subscribe_list(
+    memory: &impl Memory,
+    list: &[SubscribedRSS],
+) -> Result<(), SubscribeListError> {
+    let json = match serde_json::to_string(list) {
+        Ok(json) => json,
+        Err(err) => return Err(SubscribeListError::Json(err)),
+    };
+
+    match memory.put(SUBSCRIPTIONS_KEY, &json).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(SubscribeListError::WorkerError(err)),
+    }
+}
+
+/// Points the subscriptions of feeds that moved permanently at their new
+/// URL. `moves` pairs the old URL with the new one. The list is loaded again
+/// right before it is saved, so that subscriptions managed during a sync are
+/// kept.
+pub async fn move_subscriptions(
+    memory: &impl Memory,
+    fallback: Option<String>,
+    moves: &[(String, String)],
+) -> Result<(), SubscribeListError> {
+    let list = match load_subscribe_list(memory, fallback).await {
+        Ok(list) => list,
+        Err(err) => return Err(err),
+    };
+    let subscribed_urls: Vec<String> = list
+        .iter()
+        .map(|subscription| subscription.rss_url.clone())
+        .collect();
+    let mut moved_list: Vec<SubscribedRSS> = vec![];
+
+    for mut subscription in list {
+        if let Some((_, moved_to)) = moves
+            .iter()
+            .find(|(rss_url, _)| *rss_url == subscription.rss_url)
+        {
+            // The feed may have moved to a URL which is already subscribed
+            if subscribed_urls.contains(moved_to) {
+                continue;
+            }
+            subscription.rss_url = moved_to.clone();
+        }
+        if moved_list
+            .iter()
+            .any(|listed| listed.rss_url == subscription.rss_url)
+        {
+            continue;
+        }
+        moved_list.push(subscription);
+    }
+
+    save_subscribe_list(memory, &moved_list).await
+}
+
+pub fn parse_subscribe_list(json: &str) -> Result<Vec<SubscribedRSS>, SubscribeListError> {
+    let entries = match serde_json::from_str::<Value>(json) {
+        Ok(Value::Array(entries)) => entries,
+        Ok(_) => {
+            return Err(SubscribeListError::Invalid(
+                "subscriptions should be a JSON array".into(),
+            ))
+        }
+        Err(err) => return Err(SubscribeListError::Json(err)),
+    };
+
+    let mut list: Vec<SubscribedRSS> = vec![];
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let subscription = match serde_json::from_value::<SubscribedRSS>(entry) {
+            Ok(subscription) => subscription,
+            Err(err) => {
+                return Err(SubscribeListError::InvalidEntry {
+                    index,
+                    message: err.to_string(),
+                })
+            }
+        };
+
+        if let Err(message) = subscription.validate() {
+            return Err(SubscribeListError::InvalidEntry { index, message });
+        }
+
+        if list.iter().any(|listed| listed.rss_url == subscription.rss_url) {
+            return Err(SubscribeListError::InvalidEntry {
+                index,
+                message: format!("{} is subscribed more than once", subscription.rss_url),
+            });
+        }
+
+        list.push(subscription);
+    }
+
+    Ok(list)
 }
 
-pub fn get_subscribe_list() -> Vec<SubscribedRSS> {
-    vec![SubscribedRSS::new("https://web.dev/feed.xml", vec!["web"])]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mock::MemoryStore;
+    use wasm_bindgen_test::*;
+
+    #[test]
+    fn parse_subscriptions() {
+        let list = parse_subscribe_list(
+            r#"[{"rss_url": "https://web.dev/feed.xml", "tags": ["web"]}, {"rss_url": "https://example.com/feed.xml", "tags": []}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            list,
+            vec![
+                SubscribedRSS::new("https://web.dev/feed.xml", vec!["web"]),
+                SubscribedRSS::new("https://example.com/feed.xml", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_non_array_subscriptions() {
+        let err = parse_subscribe_list(r#"{"rss_url": "https://web.dev/feed.xml"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "subscriptions should be a JSON array");
+    }
+
+    #[test]
+    fn reject_entry_without_rss_url() {
+        let err = parse_subscribe_list(r#"[{"tags": ["web"]}]"#).unwrap_err();
+        assert!(matches!(err, SubscribeListError::InvalidEntry { index: 0, .. }));
+    }
+
+    #[test]
+    fn reject_non_http_rss_url() {
+        let err =
+            parse_subscribe_list(r#"[{"rss_url": "ftp://example.com/feed.xml", "tags": []}]"#)
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "subscription #0 is invalid: rss_url should be an http(s) URL, but got ftp scheme"
+        );
+    }
+
+    #[test]
+    fn reject_tag_with_comma() {
+        let err = parse_subscribe_list(
+            r#"[{"rss_url": "https://web.dev/feed.xml", "tags": ["web,css"]}]"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "subscription #0 is invalid: tag web,css should not contain comma"
+        );
+    }
+
+    #[test]
+    fn reject_duplicated_rss_url() {
+        let err = parse_subscribe_list(
+            r#"[{"rss_url": "https://web.dev/feed.xml", "tags": []}, {"rss_url": "https://web.dev/feed.xml", "tags": []}]"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, SubscribeListError::InvalidEntry { index: 1, .. }));
+    }
+
+    #[wasm_bindgen_test]
+    async fn move_subscriptions_to_new_url() {
+        let memory = MemoryStore::new();
+        save_subscribe_list(
+            &memory,
+            &[
+                SubscribedRSS::new("https://example.com/feed.xml", vec!["blog"]),
+                SubscribedRSS::new("https://example.com/old.xml", vec!["old"]),
+            ],
+        )
+        .await
+        .unwrap();
+        // Subscribed while the feeds were synced
+        save_subscribe_list(
+            &memory,
+            &[
+                SubscribedRSS::new("https://example.com/feed.xml", vec!["blog"]),
+                SubscribedRSS::new("https://example.com/old.xml", vec!["old"]),
+                SubscribedRSS::new("https://web.dev/feed.xml", vec!["web"]),
+            ],
+        )
+        .await
+        .unwrap();
+
+        move_subscriptions(
+            &memory,
+            None,
+            &[
+                (
+                    "https://example.com/feed.xml".to_string(),
+                    "https://blog.example.com/feed.xml".to_string(),
+                ),
+                (
+                    "https://example.com/old.xml".to_string(),
+                    "https://web.dev/feed.xml".to_string(),
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            load_subscribe_list(&memory, None).await.unwrap(),
+            vec![
+                SubscribedRSS::new("https://blog.example.com/feed.xml", vec!["blog"]),
+                SubscribedRSS::new("https://web.dev/feed.xml", vec!["web"]),
+            ]
+        );
+    }
 }
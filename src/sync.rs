@@ -0,0 +1,2225 @@
+use crate::categories::CategoryOptions;
+use crate::feed_auth::{PrefixedSecrets, Secrets};
+use crate::feed_state_memory::{self, FeedState, HealthOptions};
+use crate::http::{FetchClient, HttpClient};
+use crate::memory::{Memory, NamespacedMemory};
+use crate::notion::{self, NotionCommand, PropertyMapping};
+use crate::polling;
+use crate::rss::Rss;
+use crate::rules::{self, GlobalRules};
+use crate::schema::{self, SchemaCheck, SchemaReport};
+use crate::store;
+use crate::subscribe::{
+    self, CacheValidators, FetchOptions, FetchedRss, SubscribeListError, SubscribedError,
+    SubscribedRSS,
+};
+use crate::tenant::{self, Tenant};
+use crate::utils;
+use crate::websub::{self, WebSubOptions};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use worker::{Date, Env, Error};
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FeedStatus {
+    Synced {
+        inserted: usize,
+        failed: usize,
+        /// How many items each include or exclude rule kept out of Notion.
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        dropped: BTreeMap<String, usize>,
+    },
+    NotModified,
+    Paused,
+    /// The feed was fetched recently and asked not to be fetched again yet.
+    NotDue {
+        next_fetch_at: u64,
+    },
+    Disabled,
+    /// The feed answered 410 Gone and is disabled for good.
+    Gone,
+    BackingOff {
+        next_attempt_at: u64,
+    },
+    Failed {
+        error: String,
+        consecutive_failures: u32,
+        disabled: bool,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FeedReport {
+    pub rss_url: String,
+    #[serde(flatten)]
+    pub status: FeedStatus,
+    /// Where the feed permanently moved, its subscription follows it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TenantStatus {
+    Synced {
+        feeds: Vec<FeedReport>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        schema: Vec<SchemaReport>,
+    },
+    /// The tenant could not be synced at all, the others still were.
+    Failed { error: String },
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SyncReport {
+    pub feeds: Vec<FeedReport>,
+    /// The databases which differ from the pages created in them, when
+    /// `SCHEMA_CHECK` is set.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schema: Vec<SchemaReport>,
+    /// Why the default workspace could not be synced, its tenants still
+    /// were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tenants: BTreeMap<String, TenantStatus>,
+}
+
+pub struct SyncOptions {
+    /// How many feeds are fetched and parsed at the same time.
+    pub fetch_concurrency: usize,
+    pub fetch: FetchOptions,
+    pub health: HealthOptions,
+    /// Creates a Notion page mentioning the notify user when a feed is
+    /// disabled.
+    pub notify_broken_feeds: bool,
+    /// Subscribes to the WebSub hubs feeds advertise when set.
+    pub websub: Option<WebSubOptions>,
+    /// Tags and routes items of every feed, on top of the rules of each
+    /// subscription.
+    pub rules: GlobalRules,
+    /// Compares the databases with the property mapping before syncing.
+    pub schema_check: SchemaCheck,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            fetch_concurrency: 4,
+            fetch: FetchOptions::default(),
+            health: HealthOptions::default(),
+            notify_broken_feeds: false,
+            websub: None,
+            rules: GlobalRules::default(),
+            schema_check: SchemaCheck::Off,
+        }
+    }
+}
+
+impl SyncOptions {
+    pub fn from_env(env: &Env) -> Self {
+        let default = Self::default();
+        Self {
+            fetch_concurrency: utils::var_or(env, "FETCH_CONCURRENCY", default.fetch_concurrency),
+            fetch: FetchOptions::from_env(env),
+            health: HealthOptions {
+                backoff_base_millis: utils::var_or(
+                    env,
+                    "FEED_BACKOFF_BASE_MILLIS",
+                    default.health.backoff_base_millis,
+                ),
+                backoff_max_millis: utils::var_or(
+                    env,
+                    "FEED_BACKOFF_MAX_MILLIS",
+                    default.health.backoff_max_millis,
+                ),
+                disable_after_failures: utils::var_or(
+                    env,
+                    "FEED_DISABLE_AFTER_FAILURES",
+                    default.health.disable_after_failures,
+                ),
+            },
+            notify_broken_feeds: utils::var_or(
+                env,
+                "NOTIFY_BROKEN_FEEDS",
+                default.notify_broken_feeds,
+            ),
+            websub: WebSubOptions::from_env(env),
+            rules: GlobalRules::from_env(env),
+            schema_check: utils::var_or(env, "SCHEMA_CHECK", default.schema_check),
+        }
+    }
+
+    /// The options of the feeds of `tenant`, routed with its own routes.
+    /// Tenant feeds are only polled, the WebSub callback serves the default
+    /// workspace.
+    pub fn for_tenant(self, tenant: &Tenant) -> Self {
+        Self {
+            websub: None,
+            rules: GlobalRules {
+                routes: tenant.routes.clone(),
+                ..self.rules
+            },
+            ..self
+        }
+    }
+}
+
+/// Entry point shared by the cron trigger and the HTTP trigger. Syncs the
+/// default workspace, then every tenant of `TENANTS` and every user who
+/// connected their workspace through OAuth.
+pub async fn run(env: &Env) -> Result<SyncReport, Error> {
+    let subscriptions_kv = env.kv("SUBSCRIPTIONS")?;
+    let kv = env.kv("LATEST_PUSHED_DATES")?;
+    let tenants = tenant::all_tenants(env, &subscriptions_kv).await;
+
+    // A deployment serving tenants only has no workspace of its own
+    let mut report = if tenants.is_empty() || env.secret("notion_api_key").is_ok() {
+        match sync_default_workspace(env, &subscriptions_kv, &kv).await {
+            Ok(report) => report,
+            // Without tenants there is nothing else to sync
+            Err(err) if tenants.is_empty() => return Err(err),
+            // The tenants are still synced when the default workspace fails
+            Err(err) => {
+                worker::console_log!("failed to sync the default workspace: {}", err);
+                SyncReport {
+                    error: Some(err.to_string()),
+                    ..SyncReport::default()
+                }
+            }
+        }
+    } else {
+        SyncReport::default()
+    };
+
+    for tenant in &tenants {
+        let notion_command = tenant
+            .notion_command(env)
+            .map(|notion_command| with_env_options(env, notion_command));
+        let status = sync_tenant(
+            &FetchClient,
+            env,
+            &subscriptions_kv,
+            &kv,
+            tenant,
+            notion_command,
+            &SyncOptions::from_env(env).for_tenant(tenant),
+        )
+        .await;
+        report.tenants.insert(tenant.id.clone(), status);
+    }
+
+    Ok(report)
+}
+
+async fn sync_default_workspace(
+    env: &Env,
+    subscriptions: &impl Memory,
+    memory: &impl Memory,
+) -> Result<SyncReport, Error> {
+    let notion_command = notion_command(env)?;
+    match sync_subscriptions(
+        &FetchClient,
+        env,
+        subscriptions,
+        memory,
+        &notion_command,
+        subscribe::subscription_list_fallback(env),
+        &SyncOptions::from_env(env),
+    )
+    .await
+    {
+        Ok(report) => Ok(report),
+        Err(err) => Err(Error::RustError(err.to_string())),
+    }
+}
+
+/// Syncs the subscriptions stored in `subscriptions`, and points the ones of
+/// feeds which moved permanently at their new URL.
+async fn sync_subscriptions(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    subscriptions: &impl Memory,
+    memory: &impl Memory,
+    notion_command: &NotionCommand,
+    fallback: Option<String>,
+    options: &SyncOptions,
+) -> Result<SyncReport, SubscribeListError> {
+    let list = match subscribe::load_subscribe_list(subscriptions, fallback.clone()).await {
+        Ok(list) => list,
+        Err(err) => return Err(err),
+    };
+
+    let schema = match options.schema_check {
+        SchemaCheck::Off => vec![],
+        check => {
+            let reports = schema::check(client, notion_command, check == SchemaCheck::Fix).await;
+            reports
+                .into_iter()
+                .filter(|report| report.has_changes())
+                .inspect(|report| {
+                    worker::console_log!(
+                        "database {} differs from the property mapping: {}",
+                        report.database_id,
+                        serde_json::to_string(report).unwrap_or_default()
+                    )
+                })
+                .collect()
+        }
+    };
+
+    let mut report = sync(client, secrets, memory, notion_command, list, options).await;
+    report.schema = schema;
+
+    let moves: Vec<(String, String)> = report
+        .feeds
+        .iter()
+        .filter_map(|feed| {
+            feed.moved_to
+                .as_ref()
+                .map(|moved_to| (feed.rss_url.clone(), moved_to.clone()))
+        })
+        .collect();
+    if !moves.is_empty() {
+        match subscribe::move_subscriptions(subscriptions, fallback, &moves).await {
+            Ok(_) => {}
+            Err(err) => worker::console_log!("failed to move subscriptions: {}", err),
+        };
+    }
+
+    Ok(report)
+}
+
+/// Syncs the subscriptions of `tenant` to its own workspace. Its
+/// subscriptions and feed states are kept under its prefix in
+/// `subscriptions` and `memory`, and its feeds only read the secrets named
+/// after it. A tenant which cannot be synced is reported
+/// as failed, without keeping the other tenants from being synced.
+pub async fn sync_tenant(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    subscriptions: &impl Memory,
+    memory: &impl Memory,
+    tenant: &Tenant,
+    notion_command: Option<NotionCommand>,
+    options: &SyncOptions,
+) -> TenantStatus {
+    let notion_command = match notion_command {
+        Some(notion_command) => notion_command,
+        None => {
+            worker::console_log!("tenant {} has no Notion token", tenant.id);
+            return TenantStatus::Failed {
+                error: format!("{} secret is not set", tenant.notion_api_key_secret),
+            };
+        }
+    };
+
+    match sync_subscriptions(
+        client,
+        &PrefixedSecrets::new(secrets, tenant.secret_prefix()),
+        &NamespacedMemory::new(subscriptions, tenant.prefix()),
+        &NamespacedMemory::new(memory, tenant.prefix()),
+        &notion_command,
+        None,
+        options,
+    )
+    .await
+    {
+        Ok(report) => TenantStatus::Synced {
+            feeds: report.feeds,
+            schema: report.schema,
+        },
+        Err(err) => {
+            worker::console_log!("failed to sync tenant {}: {}", tenant.id, err);
+            TenantStatus::Failed {
+                error: err.to_string(),
+            }
+        }
+    }
+}
+
+pub fn notion_command(env: &Env) -> Result<NotionCommand, Error> {
+    let database_id = env.secret("database_id")?.to_string();
+    let notify_user_id = env.secret("notify_user_id")?.to_string();
+    let notion_api_key = env.secret("notion_api_key")?.to_string();
+
+    Ok(with_env_options(
+        env,
+        NotionCommand::build(database_id, notify_user_id, notion_api_key),
+    )
+    .with_properties(PropertyMapping::from_env(env))
+    .with_targets(notion::targets_from_env(env)))
+}
+
+/// Applies the options every workspace shares. Each one has its own token,
+/// so its own rate limit.
+pub fn with_env_options(env: &Env, notion_command: NotionCommand) -> NotionCommand {
+    notion_command
+        .with_request_interval(utils::var_or(
+            env,
+            "NOTION_REQUEST_INTERVAL_MILLIS",
+            notion::DEFAULT_REQUEST_INTERVAL_MILLIS,
+        ))
+        .with_categories(CategoryOptions::from_env(env))
+        .with_podcasts(utils::var_or(env, "PODCASTS", false))
+}
+
+enum FetchedFeed {
+    Skipped(FeedStatus),
+    NotModified {
+        max_age: Option<u64>,
+        feed_state: FeedState,
+    },
+    Modified {
+        rss: Rss,
+        validators: CacheValidators,
+        moved_to: Option<String>,
+        max_age: Option<u64>,
+        feed_state: FeedState,
+    },
+    Failed {
+        error: SubscribedError,
+        feed_state: FeedState,
+    },
+}
+
+/// Pushes the items of every subscribed feed to Notion. Feeds are fetched
+/// and parsed concurrently, up to `options.fetch_concurrency` at a time,
+/// while the Notion pages are created one by one through the rate limit of
+/// `notion_command`. A feed that cannot be fetched or parsed is recorded as
+/// failed in the report and does not keep the other feeds from being synced.
+/// It is then retried with an exponential backoff, and disabled when it
+/// keeps failing. Feeds advertising a WebSub hub are subscribed to it when
+/// `options.websub` is set.
+pub async fn sync(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    memory: &impl Memory,
+    notion_command: &NotionCommand,
+    list: Vec<SubscribedRSS>,
+    options: &SyncOptions,
+) -> SyncReport {
+    let now = Date::now().as_millis();
+
+    let fetched_feeds: Vec<FetchedFeed> = stream::iter(&list)
+        .map(|xml| fetch_feed(client, secrets, memory, xml, &options.fetch, now))
+        .buffered(options.fetch_concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut report = SyncReport::default();
+
+    for (xml, fetched_feed) in list.iter().zip(fetched_feeds) {
+        let mut moved_to = None;
+        let status = match fetched_feed {
+            FetchedFeed::Skipped(status) => status,
+            FetchedFeed::NotModified {
+                max_age,
+                mut feed_state,
+            } => {
+                feed_state.record_fetch(now, max_age, None);
+                feed_state.record_success();
+                save_feed_state(memory, &xml.rss_url, &feed_state).await;
+                FeedStatus::NotModified
+            }
+            FetchedFeed::Modified {
+                rss,
+                validators,
+                moved_to: moved,
+                max_age,
+                mut feed_state,
+            } => {
+                feed_state.record_fetch(now, max_age, Some(&rss.polling));
+                feed_state.record_hub(
+                    rss.hub_url.clone(),
+                    rss.self_url.clone().unwrap_or_else(|| xml.rss_url.clone()),
+                );
+                moved_to = moved;
+                push_feed(
+                    client,
+                    memory,
+                    notion_command,
+                    xml,
+                    &options.rules,
+                    rss,
+                    validators,
+                    moved_to.as_deref(),
+                    feed_state,
+                )
+                .await
+            }
+            FetchedFeed::Failed { error, feed_state } => {
+                record_failure(
+                    client,
+                    memory,
+                    notion_command,
+                    xml,
+                    error,
+                    feed_state,
+                    options,
+                    now,
+                )
+                .await
+            }
+        };
+
+        report.feeds.push(FeedReport {
+            rss_url: xml.rss_url.clone(),
+            status,
+            moved_to,
+        });
+    }
+
+    if let Some(websub) = &options.websub {
+        websub::renew_subscriptions(client, memory, &list, websub, now).await;
+    }
+
+    report
+}
+
+async fn fetch_feed(
+    client: &impl HttpClient,
+    secrets: &impl Secrets,
+    memory: &impl Memory,
+    xml: &SubscribedRSS,
+    options: &FetchOptions,
+    now: u64,
+) -> FetchedFeed {
+    if xml.paused {
+        return FetchedFeed::Skipped(FeedStatus::Paused);
+    }
+
+    let feed_state = feed_state_memory::get_feed_state(memory, &xml.rss_url).await;
+    if feed_state.disabled {
+        return FetchedFeed::Skipped(FeedStatus::Disabled);
+    }
+    if !feed_state.is_due(now) {
+        return FetchedFeed::Skipped(FeedStatus::BackingOff {
+            next_attempt_at: feed_state.next_attempt_at.unwrap_or(now),
+        });
+    }
+    let next_fetch_at = polling::next_fetch_at(&feed_state, xml.min_interval_minutes, now);
+    if next_fetch_at > now {
+        return FetchedFeed::Skipped(FeedStatus::NotDue { next_fetch_at });
+    }
+
+    let validators = CacheValidators {
+        etag: feed_state.etag.clone(),
+        last_modified: feed_state.last_modified.clone(),
+    };
+
+    match xml.fetch_rss(client, secrets, &validators, options).await {
+        Ok(FetchedRss::Modified {
+            rss,
+            validators,
+            moved_to,
+            max_age,
+        }) => FetchedFeed::Modified {
+            rss,
+            validators,
+            moved_to,
+            max_age,
+            feed_state,
+        },
+        Ok(FetchedRss::NotModified { max_age }) => FetchedFeed::NotModified {
+            max_age,
+            feed_state,
+        },
+        Err(error) => FetchedFeed::Failed { error, feed_state },
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record_failure(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    notion_command: &NotionCommand,
+    xml: &SubscribedRSS,
+    error: SubscribedError,
+    mut feed_state: FeedState,
+    options: &SyncOptions,
+    now: u64,
+) -> FeedStatus {
+    if let SubscribedError::Gone = error {
+        worker::console_log!("{} is gone, disabling it", xml.rss_url);
+        feed_state.mark_gone();
+        save_feed_state(memory, &xml.rss_url, &feed_state).await;
+        return FeedStatus::Gone;
+    }
+
+    let error = format!("{:?}", error);
+    worker::console_log!("failed to sync {}: {}", xml.rss_url, error);
+
+    let disabled = feed_state.record_failure(error.clone(), now, &options.health);
+    save_feed_state(memory, &xml.rss_url, &feed_state).await;
+
+    if disabled {
+        worker::console_log!(
+            "{} is disabled after {} failures in a row",
+            xml.rss_url,
+            feed_state.consecutive_failures
+        );
+        if options.notify_broken_feeds {
+            match notion_command
+                .notify_broken_feed(client, &xml.rss_url, &error)
+                .await
+            {
+                Ok(_) => {}
+                Err(err) => worker::console_log!("err: {:?}", err),
+            };
+        }
+    }
+
+    FeedStatus::Failed {
+        error,
+        consecutive_failures: feed_state.consecutive_failures,
+        disabled: feed_state.disabled,
+    }
+}
+
+async fn save_feed_state(memory: &impl Memory, rss_url: &str, feed_state: &FeedState) {
+    match feed_state_memory::put_feed_state(memory, rss_url, feed_state).await {
+        Ok(_) => {}
+        Err(err) => worker::console_log!("err: {:?}", err),
+    };
+}
+
+/// How many ids of pushed items without a date are remembered for a feed.
+const MAX_PUSHED_IDS: usize = 500;
+
+#[allow(clippy::too_many_arguments)]
+pub async fn push_feed(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    notion_command: &NotionCommand,
+    xml: &SubscribedRSS,
+    global_rules: &GlobalRules,
+    rss: Rss,
+    validators: CacheValidators,
+    moved_to: Option<&str>,
+    mut feed_state: FeedState,
+) -> FeedStatus {
+    let rss_url = xml.rss_url.clone();
+    let tagger = rules::Tagger::new(&global_rules.tag_rules, &xml.tag_rules);
+    let router = rules::Router::new(&global_rules.routes);
+
+    // Only the items published after the last push are new, every item of a
+    // feed is new the first time it is synced
+    let latest_pushed_date_millis = feed_state.latest_pushed_date;
+    let latest_pushed_date = rss.exclude_latest_published_date().as_millis();
+    let items = rss.items.iter().filter(|item| {
+        match (&item.published_date, latest_pushed_date_millis) {
+            (_, None) => true,
+            (Some(item_published_date), Some(latest_pushed_date_millis)) => {
+                item_published_date.as_millis() > latest_pushed_date_millis
+            }
+            // The watermark cannot tell whether an item without a date is new
+            (None, Some(_)) => !feed_state.pushed_ids.contains(&item.id),
+        }
+    });
+
+    // Items the rules drop still count for the watermark above
+    let filter = rules::Filter::new(&xml.include, &xml.exclude);
+    let mut dropped: BTreeMap<String, usize> = BTreeMap::new();
+    let items = items.filter(|item| match filter.drops(item) {
+        Some(rule) => {
+            *dropped.entry(rule).or_insert(0) += 1;
+            false
+        }
+        None => true,
+    });
+
+    let store_columns = items.map(|item| {
+        let tags = tagger.tags(item, &xml.tags);
+        // A matching route wins over the target of the subscription
+        let target = router.target(item, &tags).or_else(|| xml.target.clone());
+        store::StoreSchema::new(
+            &item.id,
+            &item.blog_title,
+            &item.article_title,
+            rss_url.clone(),
+            tags,
+            &item.description,
+            &item.article_url,
+            &item.published_date,
+        )
+        .with_target(target)
+        .with_categories(item.categories.clone())
+        .with_media(item.media.clone())
+        .with_cover(item.thumbnail.clone())
+        .with_icon(rss.icon_url.clone())
+    });
+
+    let mut inserted = 0;
+    let mut failed = 0;
+
+    for store_column in store_columns {
+        let res = notion_command.insert_column(client, store_column).await;
+        match res {
+            Ok(_) => inserted += 1,
+            Err(err) => {
+                failed += 1;
+                worker::console_log!("failed to create a page: {:?}", err)
+            }
+        };
+    }
+
+    // Content pushed by a hub may only hold older items, which must not move
+    // the watermark back
+    feed_state.latest_pushed_date = Some(match latest_pushed_date_millis {
+        Some(latest_pushed_date_millis) => latest_pushed_date.max(latest_pushed_date_millis),
+        None => latest_pushed_date,
+    });
+    // Content pushed by a hub may only hold some of the items, so the ids
+    // pushed before are kept after those of the feed
+    let mut pushed_ids = rss
+        .items
+        .iter()
+        .filter(|item| item.published_date.is_none())
+        .map(|item| item.id.clone())
+        .collect::<Vec<String>>();
+    for id in feed_state.pushed_ids.drain(..) {
+        if !pushed_ids.contains(&id) {
+            pushed_ids.push(id);
+        }
+    }
+    pushed_ids.truncate(MAX_PUSHED_IDS);
+    feed_state.pushed_ids = pushed_ids;
+    feed_state.etag = validators.etag;
+    feed_state.last_modified = validators.last_modified;
+    feed_state.record_success();
+
+    match moved_to {
+        // The state follows the feed to its new URL so the watermark is kept
+        Some(moved_to) => {
+            worker::console_log!("{} moved permanently to {}", rss_url, moved_to);
+            // The new URL may already be subscribed, its own state is kept then
+            // like its subscription is
+            match memory.get(moved_to).await {
+                Ok(None) => save_feed_state(memory, moved_to, &feed_state).await,
+                Ok(Some(_)) => worker::console_log!("{} already has a state", moved_to),
+                Err(err) => worker::console_log!("err: {:?}", err),
+            };
+            match memory.delete(&rss_url).await {
+                Ok(_) => {}
+                Err(err) => worker::console_log!("err: {:?}", err),
+            };
+        }
+        None => save_feed_state(memory, &rss_url, &feed_state).await,
+    };
+
+    FeedStatus::Synced {
+        inserted,
+        failed,
+        dropped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_auth::mock::SecretStore;
+    use crate::feed_auth::FeedAuth;
+    use crate::http::mock::MockClient;
+    use crate::http::HttpResponse;
+    use crate::memory::mock::MemoryStore;
+    use serde_json::{json, Value};
+    use wasm_bindgen_test::*;
+    use worker::Method;
+
+    const FEED_URL: &str = "https://example.com/feed.xml";
+    const BROKEN_FEED_URL: &str = "https://example.com/broken.xml";
+    const MOVED_FEED_URL: &str = "https://example.com/new/feed.xml";
+    const NOTION_PAGES_URL: &str = "https://api.notion.com/v1/pages";
+
+    const FEED: &str = "<rss version=\"2.0\"><channel><title>blog title</title><item><title>article title 1</title><description>article description 1</description><link>https://example.com/1</link><guid>guid 1</guid></item><item><title>article title 2</title><description>article description 2</description><link>https://example.com/2</link><guid>guid 2</guid></item></channel></rss>";
+
+    fn notion_command() -> NotionCommand {
+        NotionCommand::build(
+            "database id".to_string(),
+            "notify user id".to_string(),
+            "notion api key".to_string(),
+        )
+    }
+
+    // `guid` is generated randomly for every page, so it is blanked out
+    // before comparing request bodies.
+    fn page_body(body: &Option<String>) -> Value {
+        let mut body: Value = serde_json::from_str(body.as_ref().unwrap()).unwrap();
+        body["properties"]["guid"] = Value::Null;
+        body
+    }
+
+    fn expected_page_body(article_title: &str, description: &str, link: &str) -> Value {
+        json!({
+            "parent": { "type": "database_id", "database_id": "database id" },
+            "properties": {
+                "blog_name": {
+                    "rich_text": [{ "type": "text", "text": { "content": "blog title" } }],
+                    "type": "rich_text"
+                },
+                "article_title": {
+                    "type": "title",
+                    "title": [{ "type": "text", "text": { "content": article_title } }]
+                },
+                "tags": { "multi_select": [{ "name": "web" }] },
+                "guid": null,
+                "description": {
+                    "rich_text": [{ "type": "text", "text": { "content": description } }],
+                    "type": "rich_text"
+                },
+                "link": { "url": link }
+            }
+        })
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_feed_items_to_notion() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds,
+            vec![FeedReport {
+                rss_url: FEED_URL.to_string(),
+                status: FeedStatus::Synced {
+                    inserted: 2,
+                    failed: 0,
+                    dropped: BTreeMap::new()
+                },
+                moved_to: None,
+            }]
+        );
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+
+        assert_eq!(requests[0].method, Method::Get);
+        assert_eq!(requests[0].url, FEED_URL);
+
+        for request in &requests[1..] {
+            assert_eq!(request.method, Method::Post);
+            assert_eq!(request.url, NOTION_PAGES_URL);
+            assert_eq!(
+                request.header("Authorization"),
+                Some("Bearer notion api key")
+            );
+            assert_eq!(request.header("Notion-Version"), Some("2022-02-22"));
+            assert_eq!(request.header("Content-Type"), Some("application/json"));
+        }
+        assert_eq!(
+            page_body(&requests[1].body),
+            expected_page_body(
+                "article title 1",
+                "article description 1",
+                "https://example.com/1"
+            )
+        );
+        assert_eq!(
+            page_body(&requests[2].body),
+            expected_page_body(
+                "article title 2",
+                "article description 2",
+                "https://example.com/2"
+            )
+        );
+
+        assert!(memory.value(FEED_URL).is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn keep_syncing_after_broken_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            BROKEN_FEED_URL,
+            HttpResponse::new(200, "<html>"),
+        );
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![
+                SubscribedRSS::new(BROKEN_FEED_URL, vec![]),
+                SubscribedRSS::new(FEED_URL, vec!["web"]),
+            ],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(report.feeds.len(), 2);
+        assert_eq!(report.feeds[0].rss_url, BROKEN_FEED_URL);
+        assert!(matches!(report.feeds[0].status, FeedStatus::Failed { .. }));
+        assert_eq!(
+            report.feeds[1].status,
+            FeedStatus::Synced {
+                inserted: 2,
+                failed: 0,
+                dropped: BTreeMap::new()
+            }
+        );
+
+        // Both feeds are fetched before the Notion pages are created
+        let requests = client.requests();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| request.method.clone())
+                .collect::<Vec<_>>(),
+            vec![Method::Get, Method::Get, Method::Post, Method::Post]
+        );
+        assert_eq!(
+            feed_state_memory::get_feed_state(&memory, BROKEN_FEED_URL)
+                .await
+                .consecutive_failures,
+            1
+        );
+        assert!(memory.value(FEED_URL).is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn skip_paused_feed() {
+        let client = MockClient::new();
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let mut subscription = SubscribedRSS::new(FEED_URL, vec!["web"]);
+        subscription.paused = true;
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![subscription],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(report.feeds[0].status, FeedStatus::Paused);
+        assert!(client.requests().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn report_failed_notion_insertions() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 0,
+                failed: 2,
+                dropped: BTreeMap::new()
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn skip_unmodified_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(200, FEED)
+                .with_header("ETag", "\"v1\"")
+                .with_header("Last-Modified", "Tue, 11 Oct 2022 00:00:00 GMT"),
+        );
+        client.on(Method::Get, FEED_URL, HttpResponse::new(304, ""));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+
+        let options = SyncOptions::default();
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+
+        assert_eq!(report.feeds[0].status, FeedStatus::NotModified);
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(requests[3].method, Method::Get);
+        assert_eq!(requests[3].header("If-None-Match"), Some("\"v1\""));
+        assert_eq!(
+            requests[3].header("If-Modified-Since"),
+            Some("Tue, 11 Oct 2022 00:00:00 GMT")
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn skip_feed_fetched_within_min_interval() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || {
+            vec![SubscribedRSS {
+                min_interval_minutes: Some(60),
+                ..SubscribedRSS::new(FEED_URL, vec!["web"])
+            }]
+        };
+
+        let options = SyncOptions::default();
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+
+        let last_fetched_at = feed_state_memory::get_feed_state(&memory, FEED_URL)
+            .await
+            .last_fetched_at
+            .unwrap();
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::NotDue {
+                next_fetch_at: last_fetched_at + 60 * 60 * 1000
+            }
+        );
+        assert_eq!(client.requests().len(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    async fn honour_cache_control_of_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(200, FEED).with_header("Cache-Control", "max-age=600"),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+
+        let options = SyncOptions::default();
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+
+        assert!(matches!(report.feeds[0].status, FeedStatus::NotDue { .. }));
+        assert_eq!(
+            feed_state_memory::get_feed_state(&memory, FEED_URL)
+                .await
+                .max_age_seconds,
+            Some(600)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn identify_feed_requests() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let mut options = SyncOptions::default();
+        options.fetch.user_agent = "test agent".to_string();
+        options.fetch.accept = "application/rss+xml".to_string();
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &options,
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests[0].header("User-Agent"), Some("test agent"));
+        assert_eq!(requests[0].header("Accept"), Some("application/rss+xml"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn authenticate_private_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            format!("{}?token=s3cret", FEED_URL),
+            HttpResponse::new(200, FEED),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new().with("feed_token", "s3cret");
+        let list = vec![SubscribedRSS {
+            auth: Some(FeedAuth::Query {
+                name: "token".to_string(),
+                value_secret: "feed_token".to_string(),
+            }),
+            ..SubscribedRSS::new(FEED_URL, vec!["web"])
+        }];
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list,
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 2,
+                failed: 0,
+                dropped: BTreeMap::new()
+            }
+        );
+        assert!(memory.value(FEED_URL).is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn fail_private_feed_without_secret() {
+        let client = MockClient::new();
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = vec![SubscribedRSS {
+            auth: Some(FeedAuth::Bearer {
+                token_secret: "feed_token".to_string(),
+            }),
+            ..SubscribedRSS::new(FEED_URL, vec!["web"])
+        }];
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list,
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert!(matches!(
+            &report.feeds[0].status,
+            FeedStatus::Failed { error, .. } if error.contains("feed_token")
+        ));
+        assert!(client.requests().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_too_large_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(
+            Method::Get,
+            BROKEN_FEED_URL,
+            HttpResponse::new(200, "").with_header("Content-Length", "1048577"),
+        );
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let mut options = SyncOptions::default();
+        options.fetch.max_body_bytes = 1024 * 1024;
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![
+                SubscribedRSS::new(BROKEN_FEED_URL, vec![]),
+                SubscribedRSS::new(FEED_URL, vec![]),
+            ],
+            &options,
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Failed {
+                error: "TooLarge(1048576)".to_string(),
+                consecutive_failures: 1,
+                disabled: false,
+            }
+        );
+
+        options.fetch.max_body_bytes = 64;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec![])],
+            &options,
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Failed {
+                error: "TooLarge(64)".to_string(),
+                consecutive_failures: 1,
+                disabled: false,
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn back_off_failing_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, BROKEN_FEED_URL, HttpResponse::new(500, ""));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(BROKEN_FEED_URL, vec![])];
+        let options = SyncOptions::default();
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+
+        assert!(matches!(
+            report.feeds[0].status,
+            FeedStatus::BackingOff { .. }
+        ));
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn disable_and_notify_broken_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, BROKEN_FEED_URL, HttpResponse::new(500, ""));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(BROKEN_FEED_URL, vec![])];
+        let mut options = SyncOptions::default();
+        options.health.backoff_base_millis = 0;
+        options.health.disable_after_failures = 2;
+        options.notify_broken_feeds = true;
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Failed {
+                error: "HttpStatus(500)".to_string(),
+                consecutive_failures: 2,
+                disabled: true,
+            }
+        );
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        let body: Value = serde_json::from_str(requests[2].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            body["properties"]["article_title"]["title"][0]["text"]["content"],
+            format!("Feed is broken: {}", BROKEN_FEED_URL)
+        );
+        assert_eq!(
+            body["children"][0]["paragraph"]["rich_text"][0],
+            json!({ "type": "mention", "mention": { "type": "user", "user": { "id": "notify user id" } } })
+        );
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        assert_eq!(report.feeds[0].status, FeedStatus::Disabled);
+        assert_eq!(client.requests().len(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    async fn follow_permanently_moved_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(301, "").with_header("Location", "/new/feed.xml"),
+        );
+        client.on(Method::Get, MOVED_FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(report.feeds[0].moved_to, Some(MOVED_FEED_URL.to_string()));
+        assert!(memory.value(FEED_URL).is_none());
+        assert!(memory.value(MOVED_FEED_URL).is_some());
+
+        let requests = client.requests();
+        assert_eq!(requests[1].url, MOVED_FEED_URL);
+        assert!(!requests[0].follow_redirects);
+    }
+
+    #[wasm_bindgen_test]
+    async fn keep_state_of_feed_moved_to_subscribed_url() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(301, "").with_header("Location", "/new/feed.xml"),
+        );
+        client.on(Method::Get, MOVED_FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let subscribed_state = FeedState {
+            latest_pushed_date: Some(42),
+            ..FeedState::default()
+        };
+        feed_state_memory::put_feed_state(&memory, MOVED_FEED_URL, &subscribed_state)
+            .await
+            .unwrap();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(report.feeds[0].moved_to, Some(MOVED_FEED_URL.to_string()));
+        assert!(memory.value(FEED_URL).is_none());
+        assert_eq!(
+            feed_state_memory::get_feed_state(&memory, MOVED_FEED_URL).await,
+            subscribed_state
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn move_website_subscription_to_discovered_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            "https://example.com/",
+            HttpResponse::new(
+                200,
+                "<html><head><link rel=\"alternate\" type=\"application/atom+xml\" href=\"/feed.xml\"></head></html>",
+            ),
+        );
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new("https://example.com/", vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 2,
+                failed: 0,
+                dropped: BTreeMap::new()
+            }
+        );
+        assert_eq!(report.feeds[0].moved_to, Some(FEED_URL.to_string()));
+        assert!(memory.value(FEED_URL).is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn set_cover_and_icon_of_pages() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss version=\"2.0\"><channel><title>blog title</title><link>https://example.com/</link><image><url>https://example.com/icon.png</url></image><item><title>article title 1</title><link>https://example.com/1</link><guid>guid 1</guid><description>&lt;img src=\"/1.png\"&gt;</description></item><item><title>article title 2</title><guid>guid 2</guid></item></channel></rss>",
+            ),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+
+        let body = page_body(&requests[1].body);
+        assert_eq!(
+            body["cover"],
+            json!({ "type": "external", "external": { "url": "https://example.com/1.png" } })
+        );
+        assert_eq!(
+            body["icon"],
+            json!({ "type": "external", "external": { "url": "https://example.com/icon.png" } })
+        );
+
+        let body = page_body(&requests[2].body);
+        assert_eq!(body["cover"], Value::Null);
+        assert_eq!(
+            body["icon"],
+            json!({ "type": "external", "external": { "url": "https://example.com/icon.png" } })
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_categories_when_configured() {
+        let feed = "<feed xmlns=\"http://www.w3.org/2005/Atom\"><title>blog title</title><category term=\"Blog\"/><entry><title>article title 1</title><id>guid 1</id><category term=\"rust\" label=\"Rust, WebAssembly\"/><category term=\"JS\"/><category term=\"Go\"/></entry></feed>";
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, feed));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &SyncOptions::default(),
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            page_body(&requests[1].body)["properties"]["categories"],
+            Value::Null
+        );
+
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, feed));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let options: CategoryOptions = serde_json::from_str(
+            r#"{ "aliases": { "js": "JavaScript" }, "allow": ["Rust WebAssembly", "JavaScript", "Blog"] }"#,
+        )
+        .unwrap();
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command().with_categories(Some(options)),
+            list(),
+            &SyncOptions::default(),
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 2);
+        let body = page_body(&requests[1].body);
+        assert_eq!(
+            body["properties"]["categories"],
+            json!({ "multi_select": [
+                { "name": "Rust WebAssembly" },
+                { "name": "JavaScript" },
+                { "name": "Blog" }
+            ] })
+        );
+        assert_eq!(
+            body["properties"]["tags"],
+            json!({ "multi_select": [{ "name": "web" }] })
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn drop_items_by_rules() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss xmlns:dc=\"http://purl.org/dc/elements/1.1/\" version=\"2.0\"><channel><title>blog title</title><item><title>Rust 1.65</title><guid>guid 1</guid><pubDate>Thu, 03 Nov 2022 00:00:00 GMT</pubDate></item><item><title>[PR] Rust course</title><guid>guid 2</guid><pubDate>Fri, 04 Nov 2022 00:00:00 GMT</pubDate></item><item><title>Go 1.19</title><guid>guid 3</guid><pubDate>Sat, 05 Nov 2022 00:00:00 GMT</pubDate><dc:creator>Gopher</dc:creator></item></channel></rss>",
+            ),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = vec![SubscribedRSS {
+            include: serde_json::from_str(r#"[{ "keyword": "rust", "fields": ["title"] }]"#)
+                .unwrap(),
+            exclude: serde_json::from_str(r#"[{ "name": "ads", "regex": "^\\[PR\\]" }]"#).unwrap(),
+            ..SubscribedRSS::new(FEED_URL, vec!["web"])
+        }];
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list,
+            &SyncOptions::default(),
+        )
+        .await;
+
+        let mut dropped = BTreeMap::new();
+        dropped.insert("ads".to_string(), 1);
+        dropped.insert("include".to_string(), 1);
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 1,
+                failed: 0,
+                dropped,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["feeds"][0]["dropped"],
+            json!({ "ads": 1, "include": 1 })
+        );
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            page_body(&requests[1].body)["properties"]["article_title"]["title"][0]["text"]
+                ["content"],
+            "Rust 1.65"
+        );
+
+        // The dropped items still move the watermark
+        assert_eq!(
+            feed_state_memory::get_feed_state(&memory, FEED_URL)
+                .await
+                .latest_pushed_date,
+            Some(1667606400000)
+        );
+
+        // Neither the inserted nor the dropped items are pushed again
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 0,
+                failed: 0,
+                dropped: BTreeMap::new(),
+            }
+        );
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].method, Method::Get);
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_items_without_date_once() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss version=\"2.0\"><channel><title>blog title</title><item><title>article title 1</title><guid>guid 1</guid></item><item><title>article title 2</title><guid>guid 2</guid></item></channel></rss>",
+            ),
+        );
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss version=\"2.0\"><channel><title>blog title</title><item><title>article title 3</title><guid>guid 3</guid></item><item><title>article title 1</title><guid>guid 1</guid></item><item><title>article title 2</title><guid>guid 2</guid></item></channel></rss>",
+            ),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        for _ in 0..2 {
+            sync(
+                &client,
+                &secrets,
+                &memory,
+                &notion_command(),
+                vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+                &SyncOptions::default(),
+            )
+            .await;
+        }
+
+        // Only the item added to the feed is pushed by the second sync
+        let titles = client
+            .requests()
+            .iter()
+            .filter(|request| request.method == Method::Post)
+            .map(|request| {
+                page_body(&request.body)["properties"]["article_title"]["title"][0]["text"]
+                    ["content"]
+                    .clone()
+            })
+            .collect::<Vec<Value>>();
+        assert_eq!(
+            titles,
+            vec![
+                json!("article title 1"),
+                json!("article title 2"),
+                json!("article title 3")
+            ]
+        );
+        assert_eq!(
+            feed_state_memory::get_feed_state(&memory, FEED_URL)
+                .await
+                .pushed_ids,
+            vec!["guid 3", "guid 1", "guid 2"]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn tag_items_by_rules() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = vec![SubscribedRSS {
+            tag_rules: serde_json::from_str(
+                r#"[{ "keyword": "title 2", "fields": ["title"], "tags": ["second"] }]"#,
+            )
+            .unwrap(),
+            ..SubscribedRSS::new(FEED_URL, vec!["web"])
+        }];
+        let options = SyncOptions {
+            rules: GlobalRules {
+                tag_rules: serde_json::from_str(
+                    r#"[{ "regex": "description \\d", "fields": ["description"], "tags": ["web", "described"] }]"#,
+                )
+                .unwrap(),
+                ..GlobalRules::default()
+            },
+            ..SyncOptions::default()
+        };
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list,
+            &options,
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(
+            page_body(&requests[1].body)["properties"]["tags"],
+            json!({ "multi_select": [{ "name": "web" }, { "name": "described" }] })
+        );
+        assert_eq!(
+            page_body(&requests[2].body)["properties"]["tags"],
+            json!({ "multi_select": [
+                { "name": "web" },
+                { "name": "described" },
+                { "name": "second" }
+            ] })
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_podcast_episodes_with_audio() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" version=\"2.0\"><channel><title>podcast title</title><item><title>episode 1</title><guid>guid 1</guid><enclosure url=\"https://example.com/1.mp3?source=rss\" type=\"audio/mpeg\" length=\"1234\"/><itunes:duration>30:00</itunes:duration><itunes:episode>1</itunes:episode></item><item><title>episode 2</title><guid>guid 2</guid><enclosure url=\"https://example.com/2.aac\" type=\"audio/aac\"/></item></channel></rss>",
+            ),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command().with_podcasts(true),
+            vec![SubscribedRSS::new(FEED_URL, vec!["podcast"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+
+        let body = page_body(&requests[1].body);
+        assert_eq!(
+            body["properties"]["audio_url"],
+            json!({ "url": "https://example.com/1.mp3?source=rss" })
+        );
+        assert_eq!(body["properties"]["duration"], json!({ "number": 1800 }));
+        assert_eq!(body["properties"]["episode"], json!({ "number": 1 }));
+        assert_eq!(
+            body["children"],
+            json!([{
+                "object": "block",
+                "type": "audio",
+                "audio": {
+                    "type": "external",
+                    "external": { "url": "https://example.com/1.mp3?source=rss" }
+                }
+            }])
+        );
+
+        // Notion cannot play AAC, so the episode only gets its URL
+        let body = page_body(&requests[2].body);
+        assert_eq!(
+            body["properties"]["audio_url"],
+            json!({ "url": "https://example.com/2.aac" })
+        );
+        assert_eq!(body["properties"]["duration"], Value::Null);
+        assert_eq!(body["children"], Value::Null);
+    }
+
+    #[wasm_bindgen_test]
+    async fn leave_out_podcast_properties_unless_configured() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(
+                200,
+                "<rss xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" version=\"2.0\"><channel><title>blog title</title><item><title>slides</title><guid>guid 1</guid><enclosure url=\"https://example.com/slides.pdf\" type=\"application/pdf\"/></item><item><title>episode 1</title><guid>guid 2</guid><enclosure url=\"https://example.com/1.mp3\" type=\"audio/mpeg\"/><itunes:episode>1</itunes:episode></item></channel></rss>",
+            ),
+        );
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let secrets = SecretStore::new();
+
+        for podcasts in [false, true] {
+            sync(
+                &client,
+                &secrets,
+                &MemoryStore::new(),
+                &notion_command().with_podcasts(podcasts),
+                vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+                &SyncOptions::default(),
+            )
+            .await;
+        }
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 6);
+        for request in &requests[1..3] {
+            let body = page_body(&request.body);
+            assert_eq!(body["properties"]["audio_url"], Value::Null);
+            assert_eq!(body["properties"]["episode"], Value::Null);
+        }
+        // Only audio enclosures make an episode
+        let body = page_body(&requests[4].body);
+        assert_eq!(body["properties"]["audio_url"], Value::Null);
+        let body = page_body(&requests[5].body);
+        assert_eq!(
+            body["properties"]["audio_url"],
+            json!({ "url": "https://example.com/1.mp3" })
+        );
+        assert_eq!(body["properties"]["episode"], json!({ "number": 1 }));
+    }
+
+    #[wasm_bindgen_test]
+    async fn keep_url_of_temporarily_moved_feed() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            FEED_URL,
+            HttpResponse::new(302, "").with_header("Location", MOVED_FEED_URL),
+        );
+        client.on(Method::Get, MOVED_FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(report.feeds[0].moved_to, None);
+        assert!(memory.value(FEED_URL).is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn disable_gone_feed() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(410, ""));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = || vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+        let options = SyncOptions::default();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        assert_eq!(report.feeds[0].status, FeedStatus::Gone);
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list(),
+            &options,
+        )
+        .await;
+        assert_eq!(report.feeds[0].status, FeedStatus::Disabled);
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn route_items_to_targets() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let notion_command = notion_command()
+            .with_properties(serde_json::from_str(r#"{ "article_title": "Name" }"#).unwrap())
+            .with_targets(
+                serde_json::from_str(
+                    r#"{ "releases": { "database_id": "releases database id", "properties": { "blog_name": "Source", "guid": null } } }"#,
+                )
+                .unwrap(),
+            );
+        let options = SyncOptions {
+            rules: GlobalRules {
+                routes: serde_json::from_str(
+                    r#"[{ "target": "releases", "match": { "keyword": "title 2", "fields": ["title"] } }]"#,
+                )
+                .unwrap(),
+                ..GlobalRules::default()
+            },
+            ..SyncOptions::default()
+        };
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command,
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &options,
+        )
+        .await;
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 2,
+                failed: 0,
+                dropped: BTreeMap::new(),
+            }
+        );
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+
+        let body = page_body(&requests[1].body);
+        assert_eq!(body["parent"]["database_id"], json!("database id"));
+        assert_eq!(
+            body["properties"]["Name"]["title"][0]["text"]["content"],
+            json!("article title 1")
+        );
+        assert!(body["properties"].get("article_title").is_none());
+
+        let body: Value = serde_json::from_str(requests[2].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["parent"]["database_id"], json!("releases database id"));
+        assert_eq!(
+            body["properties"]["Source"]["rich_text"][0]["text"]["content"],
+            json!("blog title")
+        );
+        assert_eq!(
+            body["properties"]["article_title"]["title"][0]["text"]["content"],
+            json!("article title 2")
+        );
+        assert!(body["properties"].get("blog_name").is_none());
+        assert!(body["properties"].get("guid").is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn fail_items_of_unknown_targets() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let list = vec![SubscribedRSS {
+            target: Some("unknown".to_string()),
+            ..SubscribedRSS::new(FEED_URL, vec!["web"])
+        }];
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            list,
+            &SyncOptions::default(),
+        )
+        .await;
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 0,
+                failed: 2,
+                dropped: BTreeMap::new(),
+            }
+        );
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn count_pages_notion_rejects() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(
+            Method::Post,
+            NOTION_PAGES_URL,
+            HttpResponse::new(
+                400,
+                r#"{ "object": "error", "code": "validation_error", "message": "guid is not a property that exists." }"#,
+            ),
+        );
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &SyncOptions::default(),
+        )
+        .await;
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 0,
+                failed: 2,
+                dropped: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn report_schema_differences_before_syncing() {
+        let client = MockClient::new();
+        client.on(
+            Method::Get,
+            "https://api.notion.com/v1/databases/database id",
+            HttpResponse::new(
+                200,
+                r#"{ "properties": { "article_title": { "type": "title" } } }"#,
+            ),
+        );
+        let subscriptions = MemoryStore::new();
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+
+        let report = sync_subscriptions(
+            &client,
+            &secrets,
+            &subscriptions,
+            &memory,
+            &notion_command(),
+            None,
+            &SyncOptions {
+                schema_check: SchemaCheck::Report,
+                ..SyncOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.schema.len(), 1);
+        let missing: Vec<&str> = report.schema[0]
+            .missing
+            .iter()
+            .map(|diff| diff.name.as_str())
+            .collect();
+        assert_eq!(
+            missing,
+            vec![
+                "blog_name",
+                "tags",
+                "guid",
+                "description",
+                "link",
+                "published_date"
+            ]
+        );
+        assert!(report.schema[0].added.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn keep_secrets_of_others_from_tenant_feeds() {
+        let client = MockClient::new();
+        let subscriptions = MemoryStore::new();
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new()
+            .with("alice_notion_api_key", "alice token")
+            .with("notion_api_key", "worker token");
+        let alice: Tenant = serde_json::from_str(
+            r#"{ "id": "alice", "database_id": "alice database id", "notify_user_id": "alice user id", "notion_api_key_secret": "alice_notion_api_key" }"#,
+        )
+        .unwrap();
+        subscribe::save_subscribe_list(
+            &NamespacedMemory::new(&subscriptions, alice.prefix()),
+            &[SubscribedRSS {
+                auth: Some(FeedAuth::Bearer {
+                    token_secret: "notion_api_key".to_string(),
+                }),
+                ..SubscribedRSS::new(FEED_URL, vec!["web"])
+            }],
+        )
+        .await
+        .unwrap();
+
+        let status = sync_tenant(
+            &client,
+            &secrets,
+            &subscriptions,
+            &memory,
+            &alice,
+            alice.notion_command(&secrets),
+            &SyncOptions::default().for_tenant(&alice),
+        )
+        .await;
+
+        assert!(matches!(
+            &status,
+            TenantStatus::Synced { feeds, .. } if matches!(
+                &feeds[0].status,
+                FeedStatus::Failed { error, .. } if error.contains("notion_api_key")
+            )
+        ));
+        assert!(client.requests().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn sync_tenants_apart() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let subscriptions = MemoryStore::new();
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new().with("alice_notion_api_key", "alice token");
+        let tenants: Vec<Tenant> = serde_json::from_str(
+            r#"[
+                { "id": "alice", "database_id": "alice database id", "notify_user_id": "alice user id", "notion_api_key_secret": "alice_notion_api_key" },
+                { "id": "bob", "database_id": "bob database id", "notify_user_id": "bob user id", "notion_api_key_secret": "bob_notion_api_key" }
+            ]"#,
+        )
+        .unwrap();
+        for tenant in &tenants {
+            subscribe::save_subscribe_list(
+                &NamespacedMemory::new(&subscriptions, tenant.prefix()),
+                &[SubscribedRSS::new(FEED_URL, vec!["web"])],
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut statuses = vec![];
+        for tenant in &tenants {
+            statuses.push(
+                sync_tenant(
+                    &client,
+                    &secrets,
+                    &subscriptions,
+                    &memory,
+                    tenant,
+                    tenant.notion_command(&secrets),
+                    &SyncOptions::default().for_tenant(tenant),
+                )
+                .await,
+            );
+        }
+
+        assert_eq!(
+            statuses[0],
+            TenantStatus::Synced {
+                feeds: vec![FeedReport {
+                    rss_url: FEED_URL.to_string(),
+                    status: FeedStatus::Synced {
+                        inserted: 2,
+                        failed: 0,
+                        dropped: BTreeMap::new(),
+                    },
+                    moved_to: None,
+                }],
+                schema: vec![],
+            }
+        );
+        // Bob has no token, which keeps nobody else from being synced
+        assert_eq!(
+            statuses[1],
+            TenantStatus::Failed {
+                error: "bob_notion_api_key secret is not set".to_string(),
+            }
+        );
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        for request in &requests[1..] {
+            assert_eq!(request.header("Authorization"), Some("Bearer alice token"));
+            assert_eq!(
+                page_body(&request.body)["parent"]["database_id"],
+                json!("alice database id")
+            );
+        }
+
+        let key = format!("tenant:alice:{}", FEED_URL);
+        assert!(memory.value(&key).is_some());
+        assert_eq!(memory.value(FEED_URL), None);
+        assert_eq!(memory.value(&format!("tenant:bob:{}", FEED_URL)), None);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::feed_auth::Secrets;
+use crate::memory::Memory;
+use crate::notion::{NotionCommand, NotionTarget, PropertyMapping};
+use crate::oauth;
+use crate::rules::RouteRule;
+use serde::Deserialize;
+use std::collections::HashMap;
+use worker::Env;
+
+/// Someone syncing their own subscriptions to their own Notion workspace,
+/// e.g. `{ "id": "alice", "database_id": "...", "notify_user_id": "...",
+/// "notion_api_key_secret": "alice_notion_api_key" }`. It is deliberately
+/// not `Debug` so the token of an OAuth installation cannot end up in a log
+/// line.
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct Tenant {
+    /// Names the tenant in the `tenant` query parameter and in the keys of
+    /// its state.
+    pub id: String,
+    pub database_id: String,
+    pub notify_user_id: String,
+    /// The name of the secret holding the Notion token of the tenant, which
+    /// is never stored with the tenant itself.
+    pub notion_api_key_secret: String,
+    /// The token of a tenant which connected its workspace through OAuth,
+    /// never read from `TENANTS`.
+    #[serde(skip)]
+    pub access_token: Option<String>,
+    #[serde(default)]
+    pub properties: PropertyMapping,
+    /// The other databases of the workspace of the tenant, like
+    /// `NOTION_TARGETS`.
+    #[serde(default)]
+    pub targets: HashMap<String, NotionTarget>,
+    /// Used instead of `NOTION_ROUTES`, whose targets are in another
+    /// workspace.
+    #[serde(default)]
+    pub routes: Vec<RouteRule>,
+}
+
+impl Tenant {
+    /// Prepended to every key the tenant stores, so that its subscriptions
+    /// and feed states never mix with the ones of others.
+    pub fn prefix(&self) -> String {
+        format!("tenant:{}:", self.id)
+    }
+
+    /// The feeds of the tenant can only be authenticated with the secrets
+    /// whose name starts with this, e.g. `tenant_alice_feed_token`. No secret
+    /// of the worker starts with `tenant_`, so a tenant named like one, as
+    /// `notion`, cannot read it.
+    pub fn secret_prefix(&self) -> String {
+        format!("tenant_{}_", self.id)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        validate_id(&self.id)?;
+        if self.database_id.trim().is_empty() {
+            return Err(format!("database_id of tenant {} is empty", self.id));
+        }
+        if self.notion_api_key_secret.trim().is_empty() {
+            return Err(format!(
+                "notion_api_key_secret of tenant {} is empty",
+                self.id
+            ));
+        }
+        for route in &self.routes {
+            route.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// The command creating pages in the workspace of the tenant, `None` when
+    /// the secret holding its token is not set.
+    pub fn notion_command(&self, secrets: &impl Secrets) -> Option<NotionCommand> {
+        let notion_api_key = match &self.access_token {
+            Some(access_token) => access_token.clone(),
+            None => secrets.secret(&self.notion_api_key_secret)?,
+        };
+
+        Some(
+            NotionCommand::build(
+                self.database_id.clone(),
+                self.notify_user_id.clone(),
+                notion_api_key,
+            )
+            .with_properties(self.properties.clone())
+            .with_targets(self.targets.clone()),
+        )
+    }
+}
+
+pub fn validate_id(id: &str) -> Result<(), String> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "tenant id {:?} should only contain letters, digits, - and _",
+            id
+        ));
+    }
+
+    Ok(())
+}
+
+/// The tenants read as JSON from the `TENANTS` variable. Invalid tenants, and
+/// tenants reusing the id of another one, are logged and left out.
+pub fn tenants_from_env(env: &Env) -> Vec<Tenant> {
+    let tenants = match env.var("TENANTS") {
+        Ok(tenants) if !tenants.to_string().trim().is_empty() => tenants.to_string(),
+        _ => return vec![],
+    };
+
+    match serde_json::from_str(&tenants) {
+        Ok(tenants) => valid_tenants(tenants),
+        Err(err) => {
+            worker::console_log!("TENANTS is not valid, no tenant is synced: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// Whether the feeds of one tenant could read the secrets of the other, as
+/// with `alice` and `alice_bob`.
+fn shares_secrets(a: &Tenant, b: &Tenant) -> bool {
+    let (a, b) = (a.secret_prefix(), b.secret_prefix());
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+fn valid_tenants(tenants: Vec<Tenant>) -> Vec<Tenant> {
+    let mut valid: Vec<Tenant> = vec![];
+
+    for tenant in tenants {
+        if let Err(err) = tenant.validate() {
+            worker::console_log!("TENANTS has an invalid tenant: {}", err);
+            continue;
+        }
+        if valid.iter().any(|listed| listed.id == tenant.id) {
+            worker::console_log!("TENANTS has tenant {} more than once", tenant.id);
+            continue;
+        }
+        if valid.iter().any(|listed| shares_secrets(listed, &tenant)) {
+            worker::console_log!(
+                "TENANTS has tenant {} whose secrets another tenant could read",
+                tenant.id
+            );
+            continue;
+        }
+        valid.push(tenant);
+    }
+
+    valid
+}
+
+/// The tenants of `TENANTS`, followed by the users who connected their
+/// workspace through OAuth, whose installations are kept in `memory`. A
+/// user whose id is taken by `TENANTS` is left out.
+pub async fn all_tenants(env: &Env, memory: &impl Memory) -> Vec<Tenant> {
+    let mut tenants = tenants_from_env(env);
+
+    for installed in oauth::installed_tenants(memory).await {
+        if tenants.iter().any(|tenant| tenant.id == installed.id) {
+            worker::console_log!(
+                "tenant {} is configured in TENANTS, its OAuth installation is ignored",
+                installed.id
+            );
+            continue;
+        }
+        if tenants
+            .iter()
+            .any(|tenant| shares_secrets(tenant, &installed))
+        {
+            worker::console_log!(
+                "tenant {} could read the secrets of another tenant, its OAuth installation is ignored",
+                installed.id
+            );
+            continue;
+        }
+        tenants.push(installed);
+    }
+
+    tenants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_auth::mock::SecretStore;
+
+    fn tenant(json: &str) -> Tenant {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn validate_tenants() {
+        let alice = tenant(
+            r#"{ "id": "alice", "database_id": "alice database id", "notify_user_id": "alice user id", "notion_api_key_secret": "alice_notion_api_key" }"#,
+        );
+        assert!(alice.validate().is_ok());
+        assert_eq!(alice.prefix(), "tenant:alice:");
+        assert_eq!(alice.secret_prefix(), "tenant_alice_");
+        // A tenant named like a secret of the worker cannot read it
+        assert!(!Tenant {
+            id: "notion".to_string(),
+            ..alice.clone()
+        }
+        .secret_prefix()
+        .starts_with("notion_"));
+
+        assert!(Tenant {
+            id: "alice:bob".to_string(),
+            ..alice.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Tenant {
+            id: "".to_string(),
+            ..alice.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Tenant {
+            notion_api_key_secret: " ".to_string(),
+            ..alice.clone()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn leave_out_invalid_and_duplicated_tenants() {
+        let tenants: Vec<Tenant> = serde_json::from_str(
+            r#"[
+                { "id": "alice", "database_id": "a", "notify_user_id": "a", "notion_api_key_secret": "alice_key" },
+                { "id": "alice", "database_id": "b", "notify_user_id": "b", "notion_api_key_secret": "other_key" },
+                { "id": "", "database_id": "c", "notify_user_id": "c", "notion_api_key_secret": "c_key" },
+                { "id": "bob", "database_id": "d", "notify_user_id": "d", "notion_api_key_secret": "bob_key" },
+                { "id": "alice_bob", "database_id": "e", "notify_user_id": "e", "notion_api_key_secret": "alice_bob_key" }
+            ]"#,
+        )
+        .unwrap();
+
+        let ids: Vec<String> = valid_tenants(tenants)
+            .into_iter()
+            .map(|tenant| tenant.id)
+            .collect();
+        assert_eq!(ids, vec!["alice", "bob"]);
+    }
+
+    #[test]
+    fn build_notion_command_from_secret() {
+        let alice = tenant(
+            r#"{ "id": "alice", "database_id": "alice database id", "notify_user_id": "alice user id", "notion_api_key_secret": "alice_notion_api_key" }"#,
+        );
+
+        assert!(alice.notion_command(&SecretStore::new()).is_none());
+
+        let notion_command = alice
+            .notion_command(&SecretStore::new().with("alice_notion_api_key", "alice token"))
+            .unwrap();
+        assert_eq!(notion_command.database_id, "alice database id");
+        assert_eq!(notion_command.notify_user_id, "alice user id");
+        assert_eq!(notion_command.notion_api_key, "alice token");
+    }
+}
@@ -1,4 +1,8 @@
 use cfg_if::cfg_if;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use worker::Env;
 
 cfg_if! {
     // https://github.com/rustwasm/console_error_panic_hook#readme
@@ -10,3 +14,31 @@ cfg_if! {
         pub fn set_panic_hook() {}
     }
 }
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+}
+
+pub async fn sleep(millis: u64) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, millis as i32);
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+/// Reads the `name` variable from `wrangler.toml`, falling back to `default`
+/// when it is missing or cannot be parsed.
+pub fn var_or<T: FromStr>(env: &Env, name: &str, default: T) -> T {
+    match env.var(name) {
+        Ok(var) => match var.to_string().parse() {
+            Ok(value) => value,
+            Err(_) => {
+                worker::console_log!("{} is not valid, using the default value", name);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
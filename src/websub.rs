@@ -0,0 +1,781 @@
+use crate::feed_state_memory::{self, FeedState};
+use crate::http::{FetchClient, HttpClient, HttpRequest};
+use crate::memory::Memory;
+use crate::notion::NotionCommand;
+use crate::rss::Rss;
+use crate::rules::GlobalRules;
+use crate::subscribe::{self, CacheValidators, SubscribeListError, SubscribedRSS};
+use crate::sync::{self, FeedStatus};
+use crate::utils;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use std::fmt;
+use url::form_urlencoded;
+use worker::{Date, Env, Error, Method, Request, Response, Result, RouteContext, Url};
+
+/// A subscription which was not verified by the hub is asked again after
+/// this long.
+const RETRY_AFTER_MILLIS: u64 = 60 * 60 * 1000;
+
+pub struct WebSubOptions {
+    /// The public URL of the `/websub/callback` route.
+    pub callback_url: String,
+    /// Every feed is given its own secret derived from this one, so that the
+    /// hub can sign the content it pushes.
+    pub secret: String,
+    pub lease_seconds: u64,
+    /// Leases are renewed this long before they end.
+    pub renew_before_millis: u64,
+}
+
+impl WebSubOptions {
+    /// WebSub is only enabled when both the `WEBSUB_CALLBACK_URL` variable
+    /// and the `websub_secret` secret are set.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let callback_url = match env.var("WEBSUB_CALLBACK_URL") {
+            Ok(callback_url) if !callback_url.to_string().is_empty() => callback_url.to_string(),
+            _ => return None,
+        };
+        let secret = match env.secret("websub_secret") {
+            Ok(secret) if !secret.to_string().is_empty() => secret.to_string(),
+            _ => {
+                worker::console_log!("websub_secret secret is not set, WebSub is disabled");
+                return None;
+            }
+        };
+
+        Some(Self {
+            callback_url,
+            secret,
+            lease_seconds: utils::var_or(env, "WEBSUB_LEASE_SECONDS", 10 * 24 * 60 * 60),
+            renew_before_millis: 24 * 60 * 60 * 1000,
+        })
+    }
+
+    /// The callback of a feed carries its subscription URL, which tells the
+    /// callback what the hub is talking about.
+    fn callback_url(&self, rss_url: &str) -> String {
+        match Url::parse(&self.callback_url) {
+            Ok(mut callback_url) => {
+                callback_url.query_pairs_mut().append_pair("topic", rss_url);
+                callback_url.to_string()
+            }
+            Err(_) => self.callback_url.clone(),
+        }
+    }
+
+    fn topic_secret(&self, rss_url: &str) -> String {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(rss_url.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSubError {
+    /// The callback is about a feed which is not subscribed, or whose
+    /// subscription was not asked for.
+    NotFound(String),
+    Invalid(String),
+    /// The pushed content is not signed with the secret of the feed.
+    Signature,
+    Store(SubscribeListError),
+}
+
+impl fmt::Display for WebSubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSubError::NotFound(rss_url) => {
+                write!(f, "{} has no WebSub subscription", rss_url)
+            }
+            WebSubError::Invalid(message) => write!(f, "{}", message),
+            WebSubError::Signature => write!(f, "signature does not match"),
+            WebSubError::Store(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+pub fn needs_subscription(feed_state: &FeedState, now: u64, options: &WebSubOptions) -> bool {
+    if feed_state.websub_hub.is_none() || feed_state.disabled {
+        return false;
+    }
+
+    let expiring = match feed_state.websub_lease_expires_at {
+        Some(lease_expires_at) => {
+            lease_expires_at <= now.saturating_add(options.renew_before_millis)
+        }
+        None => true,
+    };
+    let pending = match feed_state.websub_requested_at {
+        Some(requested_at) => now < requested_at.saturating_add(RETRY_AFTER_MILLIS),
+        None => false,
+    };
+
+    expiring && !pending
+}
+
+/// Asks the hub of the feed to push its content to the callback.
+pub async fn subscribe(
+    client: &impl HttpClient,
+    rss_url: &str,
+    feed_state: &mut FeedState,
+    options: &WebSubOptions,
+    now: u64,
+) -> std::result::Result<(), Error> {
+    let (hub_url, topic) = match (&feed_state.websub_hub, &feed_state.websub_topic) {
+        (Some(hub_url), Some(topic)) => (hub_url.clone(), topic.clone()),
+        _ => return Ok(()),
+    };
+
+    let body = form_urlencoded::Serializer::new(String::new())
+        .append_pair("hub.callback", &options.callback_url(rss_url))
+        .append_pair("hub.mode", "subscribe")
+        .append_pair("hub.topic", &topic)
+        .append_pair("hub.lease_seconds", &options.lease_seconds.to_string())
+        .append_pair("hub.secret", &options.topic_secret(rss_url))
+        .finish();
+    send_to_hub(client, &hub_url, body).await?;
+
+    feed_state.websub_requested_at = Some(now);
+    Ok(())
+}
+
+/// Asks the hub of a feed which is not synced anymore to stop pushing its
+/// content. Feeds without a lease or a pending subscription are left alone.
+pub async fn unsubscribe(
+    client: &impl HttpClient,
+    rss_url: &str,
+    feed_state: &FeedState,
+    options: &WebSubOptions,
+) -> std::result::Result<(), Error> {
+    if feed_state.websub_lease_expires_at.is_none() && feed_state.websub_requested_at.is_none() {
+        return Ok(());
+    }
+    let (hub_url, topic) = match (&feed_state.websub_hub, &feed_state.websub_topic) {
+        (Some(hub_url), Some(topic)) => (hub_url.clone(), topic.clone()),
+        _ => return Ok(()),
+    };
+
+    let body = form_urlencoded::Serializer::new(String::new())
+        .append_pair("hub.callback", &options.callback_url(rss_url))
+        .append_pair("hub.mode", "unsubscribe")
+        .append_pair("hub.topic", &topic)
+        .finish();
+    send_to_hub(client, &hub_url, body).await
+}
+
+async fn send_to_hub(
+    client: &impl HttpClient,
+    hub_url: &str,
+    body: String,
+) -> std::result::Result<(), Error> {
+    let request = HttpRequest::new(Method::Post, hub_url)
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(body);
+
+    let response = client.send(request).await?;
+    if !(200..300).contains(&response.status) {
+        return Err(Error::RustError(format!(
+            "hub {} answered {}: {}",
+            hub_url, response.status, response.body
+        )));
+    }
+
+    Ok(())
+}
+
+/// Subscribes to the hubs of the feeds which have no lease yet, and renews
+/// the leases about to end.
+pub async fn renew_subscriptions(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    list: &[SubscribedRSS],
+    options: &WebSubOptions,
+    now: u64,
+) {
+    for xml in list.iter().filter(|xml| !xml.paused) {
+        let mut feed_state = feed_state_memory::get_feed_state(memory, &xml.rss_url).await;
+        if !needs_subscription(&feed_state, now, options) {
+            continue;
+        }
+
+        match subscribe(client, &xml.rss_url, &mut feed_state, options, now).await {
+            Ok(_) => {}
+            Err(err) => {
+                worker::console_log!("failed to subscribe {} to its hub: {:?}", xml.rss_url, err);
+                // Tried again after a while rather than on every run
+                feed_state.websub_requested_at = Some(now);
+            }
+        };
+
+        match feed_state_memory::put_feed_state(memory, &xml.rss_url, &feed_state).await {
+            Ok(_) => {}
+            Err(err) => worker::console_log!("err: {:?}", err),
+        };
+    }
+}
+
+/// The `hub.*` parameters of a verification of intent.
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub mode: String,
+    pub topic: String,
+    pub challenge: String,
+    pub lease_seconds: Option<u64>,
+}
+
+/// Checks the hub is verifying a subscription that was asked for, and
+/// returns the challenge to echo back.
+pub async fn verify_intent(
+    memory: &impl Memory,
+    list: &[SubscribedRSS],
+    rss_url: &str,
+    verification: &Verification,
+    now: u64,
+) -> std::result::Result<String, WebSubError> {
+    let subscribed = list.iter().any(|xml| xml.rss_url == rss_url && !xml.paused);
+    let mut feed_state = feed_state_memory::get_feed_state(memory, rss_url).await;
+
+    match verification.mode.as_str() {
+        "subscribe" => {
+            if !subscribed
+                || feed_state.websub_requested_at.is_none()
+                || feed_state.websub_topic.as_deref() != Some(verification.topic.as_str())
+            {
+                return Err(WebSubError::NotFound(rss_url.to_string()));
+            }
+
+            let lease_seconds = match verification.lease_seconds {
+                Some(lease_seconds) => lease_seconds,
+                None => {
+                    return Err(WebSubError::Invalid(
+                        "hub.lease_seconds is required to subscribe".into(),
+                    ))
+                }
+            };
+            feed_state.websub_lease_expires_at =
+                Some(now.saturating_add(lease_seconds.saturating_mul(1000)));
+            feed_state.websub_requested_at = None;
+        }
+        "unsubscribe" => {
+            // Only feeds which are not synced anymore are let go
+            if subscribed {
+                return Err(WebSubError::NotFound(rss_url.to_string()));
+            }
+            return Ok(verification.challenge.clone());
+        }
+        "denied" => {
+            worker::console_log!("hub denied the subscription of {}", rss_url);
+            // Asked again after a while, the feed keeps being polled meanwhile
+            feed_state.websub_lease_expires_at = None;
+            feed_state.websub_requested_at = Some(now);
+        }
+        mode => return Err(WebSubError::Invalid(format!("unknown hub.mode {}", mode))),
+    };
+
+    match feed_state_memory::put_feed_state(memory, rss_url, &feed_state).await {
+        Ok(_) => Ok(verification.challenge.clone()),
+        Err(err) => Err(WebSubError::Invalid(format!("{:?}", err))),
+    }
+}
+
+fn verify_mac<M: Mac + KeyInit>(key: &[u8], body: &[u8], signature: &[u8]) -> bool {
+    match <M as KeyInit>::new_from_slice(key) {
+        Ok(mut mac) => {
+            mac.update(body);
+            mac.verify_slice(signature).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Checks an `X-Hub-Signature: <method>=<hex digest>` header against the
+/// HMAC of `body`.
+pub fn verify_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let (method, digest) = match signature.and_then(|signature| signature.split_once('=')) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let digest = match hex::decode(digest.trim()) {
+        Ok(digest) => digest,
+        Err(_) => return false,
+    };
+    let key = secret.as_bytes();
+
+    match method.trim() {
+        "sha1" => verify_mac::<Hmac<Sha1>>(key, body, &digest),
+        "sha256" => verify_mac::<Hmac<Sha256>>(key, body, &digest),
+        "sha384" => verify_mac::<Hmac<Sha384>>(key, body, &digest),
+        "sha512" => verify_mac::<Hmac<Sha512>>(key, body, &digest),
+        _ => false,
+    }
+}
+
+/// Pushes the content the hub distributed to Notion, the same way a polled
+/// feed is. Both share the watermark of the feed, so an item is created once
+/// whether it is pushed, polled or both.
+#[allow(clippy::too_many_arguments)]
+pub async fn receive_content(
+    client: &impl HttpClient,
+    memory: &impl Memory,
+    notion_command: &NotionCommand,
+    list: &[SubscribedRSS],
+    rules: &GlobalRules,
+    rss_url: &str,
+    signature: Option<&str>,
+    body: &[u8],
+    options: &WebSubOptions,
+) -> std::result::Result<FeedStatus, WebSubError> {
+    let xml = match list
+        .iter()
+        .find(|xml| xml.rss_url == rss_url && !xml.paused)
+    {
+        Some(xml) => xml,
+        None => return Err(WebSubError::NotFound(rss_url.to_string())),
+    };
+
+    if !verify_signature(&options.topic_secret(rss_url), signature, body) {
+        return Err(WebSubError::Signature);
+    }
+
+    let rss = match std::str::from_utf8(body) {
+        Ok(body) => match Rss::from_xml(body) {
+            Ok(rss) => rss,
+            Err(err) => return Err(WebSubError::Invalid(format!("{:?}", err))),
+        },
+        Err(err) => return Err(WebSubError::Invalid(err.to_string())),
+    };
+
+    let feed_state = feed_state_memory::get_feed_state(memory, rss_url).await;
+    let validators = CacheValidators {
+        etag: feed_state.etag.clone(),
+        last_modified: feed_state.last_modified.clone(),
+    };
+
+    Ok(sync::push_feed(
+        client,
+        memory,
+        notion_command,
+        xml,
+        rules,
+        rss,
+        validators,
+        None,
+        feed_state,
+    )
+    .await)
+}
+
+fn topic_param(req: &Request) -> Result<Option<String>> {
+    let url = req.url()?;
+    let topic = url
+        .query_pairs()
+        .find(|(key, _)| key == "topic")
+        .map(|(_, value)| value.to_string());
+    Ok(topic)
+}
+
+async fn load(env: &Env) -> std::result::Result<Vec<SubscribedRSS>, WebSubError> {
+    let subscriptions_kv = match env.kv("SUBSCRIPTIONS") {
+        Ok(subscriptions_kv) => subscriptions_kv,
+        Err(err) => return Err(WebSubError::Store(SubscribeListError::WorkerError(err))),
+    };
+
+    match subscribe::load_subscribe_list(
+        &subscriptions_kv,
+        subscribe::subscription_list_fallback(env),
+    )
+    .await
+    {
+        Ok(list) => Ok(list),
+        Err(err) => Err(WebSubError::Store(err)),
+    }
+}
+
+/// `GET /websub/callback`, the hub verifying a subscription.
+pub async fn verify_callback(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let rss_url = match topic_param(&req)? {
+        Some(rss_url) => rss_url,
+        None => return Response::error("not found", 404),
+    };
+
+    let url = req.url()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let verification = Verification {
+        mode: param("hub.mode").unwrap_or_default(),
+        topic: param("hub.topic").unwrap_or_default(),
+        challenge: param("hub.challenge").unwrap_or_default(),
+        lease_seconds: param("hub.lease_seconds")
+            .and_then(|lease_seconds| lease_seconds.parse::<u64>().ok()),
+    };
+
+    let list = match load(&ctx.env).await {
+        Ok(list) => list,
+        Err(err) => {
+            worker::console_log!("err: {}", err);
+            return Response::error("internal server error", 500);
+        }
+    };
+    let kv = ctx.kv("LATEST_PUSHED_DATES")?;
+
+    match verify_intent(&kv, &list, &rss_url, &verification, Date::now().as_millis()).await {
+        Ok(challenge) => Response::ok(challenge),
+        Err(WebSubError::NotFound(_)) => Response::error("not found", 404),
+        Err(err) => Response::error(err.to_string(), 400),
+    }
+}
+
+/// `POST /websub/callback`, the hub distributing new content.
+pub async fn receive_callback(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let rss_url = match topic_param(&req)? {
+        Some(rss_url) => rss_url,
+        None => return Response::error("not found", 404),
+    };
+    let options = match WebSubOptions::from_env(&ctx.env) {
+        Some(options) => options,
+        None => return Response::error("not found", 404),
+    };
+
+    let signature = req.headers().get("X-Hub-Signature")?;
+    let body = req.bytes().await?;
+
+    let list = match load(&ctx.env).await {
+        Ok(list) => list,
+        Err(err) => {
+            worker::console_log!("err: {}", err);
+            return Response::error("internal server error", 500);
+        }
+    };
+    let kv = ctx.kv("LATEST_PUSHED_DATES")?;
+    let notion_command = sync::notion_command(&ctx.env)?;
+
+    match receive_content(
+        &FetchClient,
+        &kv,
+        &notion_command,
+        &list,
+        &GlobalRules::from_env(&ctx.env),
+        &rss_url,
+        signature.as_deref(),
+        &body,
+        &options,
+    )
+    .await
+    {
+        Ok(status) => Response::from_json(&status),
+        // Tells the hub to stop pushing a feed which is not synced anymore
+        Err(WebSubError::NotFound(_)) => Response::error("gone", 410),
+        // The content is ignored, but acknowledged so a forger learns nothing
+        Err(WebSubError::Signature) => {
+            worker::console_log!("ignored content for {} with a wrong signature", rss_url);
+            Ok(Response::empty()?.with_status(202))
+        }
+        Err(err) => Response::error(err.to_string(), 400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_auth::mock::SecretStore;
+    use crate::http::mock::MockClient;
+    use crate::http::HttpResponse;
+    use crate::memory::mock::MemoryStore;
+    use crate::sync::SyncOptions;
+    use std::collections::BTreeMap;
+    use wasm_bindgen_test::*;
+
+    const FEED_URL: &str = "https://example.com/feed.xml";
+    const HUB_URL: &str = "https://hub.example.com/";
+    const NOTION_PAGES_URL: &str = "https://api.notion.com/v1/pages";
+    const FEED: &str = "<rss xmlns:atom=\"http://www.w3.org/2005/Atom\" version=\"2.0\"><channel><title>blog title</title><atom:link rel=\"hub\" href=\"https://hub.example.com/\"/><atom:link rel=\"self\" href=\"https://example.com/feed.xml\"/><item><title>article title 1</title><description>article description 1</description><link>https://example.com/1</link><guid>guid 1</guid></item></channel></rss>";
+    const MINUTE: u64 = 60 * 1000;
+
+    fn options() -> WebSubOptions {
+        WebSubOptions {
+            callback_url: "https://worker.example.com/websub/callback".to_string(),
+            secret: "websub secret".to_string(),
+            lease_seconds: 600,
+            renew_before_millis: MINUTE,
+        }
+    }
+
+    fn notion_command() -> NotionCommand {
+        NotionCommand::build(
+            "database id".to_string(),
+            "notify user id".to_string(),
+            "notion api key".to_string(),
+        )
+    }
+
+    fn subscribed_state() -> FeedState {
+        FeedState {
+            websub_hub: Some(HUB_URL.to_string()),
+            websub_topic: Some(FEED_URL.to_string()),
+            ..FeedState::default()
+        }
+    }
+
+    fn sign(secret: &str, body: &str) -> String {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn verify_known_signatures() {
+        let body = b"The quick brown fox jumps over the lazy dog";
+
+        assert!(verify_signature(
+            "key",
+            Some("sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9"),
+            body
+        ));
+        assert!(verify_signature(
+            "key",
+            Some("sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"),
+            body
+        ));
+        assert!(!verify_signature(
+            "other key",
+            Some("sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9"),
+            body
+        ));
+        assert!(!verify_signature(
+            "key",
+            Some("md5=80070713463e7749b90c2dc24911e275"),
+            body
+        ));
+        assert!(!verify_signature("key", None, body));
+    }
+
+    #[test]
+    fn renew_lease_before_it_ends() {
+        let mut feed_state = subscribed_state();
+        assert!(needs_subscription(&feed_state, 0, &options()));
+
+        feed_state.websub_requested_at = Some(0);
+        assert!(!needs_subscription(&feed_state, MINUTE, &options()));
+        assert!(needs_subscription(&feed_state, 60 * MINUTE, &options()));
+
+        feed_state.websub_requested_at = None;
+        feed_state.websub_lease_expires_at = Some(10 * MINUTE);
+        assert!(!needs_subscription(&feed_state, 8 * MINUTE, &options()));
+        assert!(needs_subscription(&feed_state, 9 * MINUTE, &options()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn subscribe_to_advertised_hub() {
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, FEED));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        client.on(Method::Post, HUB_URL, HttpResponse::new(202, ""));
+        let memory = MemoryStore::new();
+        let secrets = SecretStore::new();
+        let options = SyncOptions {
+            websub: Some(options()),
+            ..SyncOptions::default()
+        };
+
+        sync::sync(
+            &client,
+            &secrets,
+            &memory,
+            &notion_command(),
+            vec![SubscribedRSS::new(FEED_URL, vec!["web"])],
+            &options,
+        )
+        .await;
+
+        let requests = client.requests();
+        let hub_request = requests
+            .iter()
+            .find(|request| request.url == HUB_URL)
+            .unwrap();
+        let params: Vec<(String, String)> =
+            form_urlencoded::parse(hub_request.body.as_ref().unwrap().as_bytes())
+                .into_owned()
+                .collect();
+        assert_eq!(
+            params,
+            vec![
+                (
+                    "hub.callback".to_string(),
+                    "https://worker.example.com/websub/callback?topic=https%3A%2F%2Fexample.com%2Ffeed.xml"
+                        .to_string()
+                ),
+                ("hub.mode".to_string(), "subscribe".to_string()),
+                ("hub.topic".to_string(), FEED_URL.to_string()),
+                ("hub.lease_seconds".to_string(), "600".to_string()),
+                ("hub.secret".to_string(), options().topic_secret(FEED_URL)),
+            ]
+        );
+
+        let feed_state = feed_state_memory::get_feed_state(&memory, FEED_URL).await;
+        assert_eq!(feed_state.websub_hub, Some(HUB_URL.to_string()));
+        assert!(feed_state.websub_requested_at.is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn verify_requested_subscription() {
+        let memory = MemoryStore::new();
+        let feed_state = FeedState {
+            websub_requested_at: Some(0),
+            ..subscribed_state()
+        };
+        feed_state_memory::put_feed_state(&memory, FEED_URL, &feed_state)
+            .await
+            .unwrap();
+        let list = vec![SubscribedRSS::new(FEED_URL, vec![])];
+        let verification = Verification {
+            mode: "subscribe".to_string(),
+            topic: FEED_URL.to_string(),
+            challenge: "challenge".to_string(),
+            lease_seconds: Some(600),
+        };
+
+        let challenge = verify_intent(&memory, &list, FEED_URL, &verification, MINUTE)
+            .await
+            .unwrap();
+
+        assert_eq!(challenge, "challenge");
+        let feed_state = feed_state_memory::get_feed_state(&memory, FEED_URL).await;
+        assert_eq!(feed_state.websub_lease_expires_at, Some(11 * MINUTE));
+        assert_eq!(feed_state.websub_requested_at, None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_unrequested_subscription() {
+        let memory = MemoryStore::new();
+        feed_state_memory::put_feed_state(&memory, FEED_URL, &subscribed_state())
+            .await
+            .unwrap();
+        let list = vec![SubscribedRSS::new(FEED_URL, vec![])];
+        let verification = Verification {
+            mode: "subscribe".to_string(),
+            topic: FEED_URL.to_string(),
+            challenge: "challenge".to_string(),
+            lease_seconds: Some(600),
+        };
+
+        let err = verify_intent(&memory, &list, FEED_URL, &verification, MINUTE)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, WebSubError::NotFound(_)));
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_received_content_to_notion() {
+        let client = MockClient::new();
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let list = vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+        let signature = sign(&options().topic_secret(FEED_URL), FEED);
+
+        let status = receive_content(
+            &client,
+            &memory,
+            &notion_command(),
+            &list,
+            &GlobalRules::default(),
+            FEED_URL,
+            Some(&signature),
+            FEED.as_bytes(),
+            &options(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            status,
+            FeedStatus::Synced {
+                inserted: 1,
+                failed: 0,
+                dropped: BTreeMap::new()
+            }
+        );
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn push_items_once_when_pushed_then_polled() {
+        let feed = "<rss version=\"2.0\"><channel><title>blog title</title><item><title>article title 1</title><link>https://example.com/1</link><guid>guid 1</guid><pubDate>Thu, 03 Nov 2022 00:00:00 GMT</pubDate></item></channel></rss>";
+        let client = MockClient::new();
+        client.on(Method::Get, FEED_URL, HttpResponse::new(200, feed));
+        client.on(Method::Post, NOTION_PAGES_URL, HttpResponse::new(200, "{}"));
+        let memory = MemoryStore::new();
+        let list = vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+        let signature = sign(&options().topic_secret(FEED_URL), feed);
+
+        receive_content(
+            &client,
+            &memory,
+            &notion_command(),
+            &list,
+            &GlobalRules::default(),
+            FEED_URL,
+            Some(&signature),
+            feed.as_bytes(),
+            &options(),
+        )
+        .await
+        .unwrap();
+        let report = sync::sync(
+            &client,
+            &SecretStore::new(),
+            &memory,
+            &notion_command(),
+            list,
+            &SyncOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            report.feeds[0].status,
+            FeedStatus::Synced {
+                inserted: 0,
+                failed: 0,
+                dropped: BTreeMap::new()
+            }
+        );
+        let pages = client
+            .requests()
+            .iter()
+            .filter(|request| request.url == NOTION_PAGES_URL)
+            .count();
+        assert_eq!(pages, 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn ignore_content_with_wrong_signature() {
+        let client = MockClient::new();
+        let memory = MemoryStore::new();
+        let list = vec![SubscribedRSS::new(FEED_URL, vec!["web"])];
+        let signature = sign("websub secret", FEED);
+
+        let err = receive_content(
+            &client,
+            &memory,
+            &notion_command(),
+            &list,
+            &GlobalRules::default(),
+            FEED_URL,
+            Some(&signature),
+            FEED.as_bytes(),
+            &options(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, WebSubError::Signature));
+        assert!(client.requests().is_empty());
+    }
+}